@@ -0,0 +1,240 @@
+//! Inspect and manage games and players, uses same config as server. Only
+//! MongoDB is used.
+
+use std::process::exit;
+
+use bson::{doc, DateTime};
+use futures::TryStreamExt;
+use lishuuro::{
+    config::Config,
+    database::{
+        cleanup::delete_unused_anons,
+        clock::queries::{get_game_db, update_entire_game},
+        model::{GameStatus, Mongo, Role, ShuuroGame},
+        replay::GameReplay,
+        roles::change_role,
+    },
+};
+use mongodb::options::FindOptions;
+
+const USAGE: &str = "usage: lishuuro-admin <command>
+
+commands:
+  games                       list unfinished games
+  timeline <id>               show every move of game with clocks
+  end <id> <status> [result]  end game or change its status, result 0, 1
+                              or 2 is required for statuses with winner;
+                              unfinished game must not be owned by running
+                              server, stop it or use POST /admin/games/<id>/end
+  delete-anons [days]         delete anonymous players without games (2 days)
+  grant <username> <role>     grant admin or moderator role
+  revoke <username> <role>    revoke role
+  stats                       count players, games and challenges";
+
+/// Written to audit log as actor.
+const ACTOR: &str = "admin-cli";
+
+#[tokio::main]
+async fn main() {
+    dotenv::dotenv().ok();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+    let mongo = Mongo::new(&config.mongo, &config.mongo_db).await;
+    let res = match args.as_slice() {
+        ["games"] => games(&mongo).await,
+        ["timeline", id] => timeline(&mongo, id).await,
+        ["end", id, status] => end(&mongo, id, status, None).await,
+        ["end", id, status, result] => end(&mongo, id, status, Some(result)).await,
+        ["delete-anons"] => delete_anons(&mongo, "2").await,
+        ["delete-anons", days] => delete_anons(&mongo, days).await,
+        ["grant", username, role] => role(&mongo, username, role, true).await,
+        ["revoke", username, role] => role(&mongo, username, role, false).await,
+        ["stats"] => stats(&mongo).await,
+        _ => Err(String::from(USAGE)),
+    };
+    if let Err(e) = res {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
+
+async fn games(mongo: &Mongo) -> Result<(), String> {
+    let options = FindOptions::builder()
+        .sort(doc! {"last_clock": -1})
+        .build();
+    let games: Vec<ShuuroGame> = mongo
+        .games
+        .find(doc! {"status": GameStatus::unfinished_filter()})
+        .with_options(options)
+        .await
+        .map_err(|e| e.to_string())?
+        .try_collect()
+        .await
+        .map_err(|e| e.to_string())?;
+    for game in &games {
+        println!(
+            "{} {} vs {} variant {} stage {} {} {}",
+            game._id,
+            game.players[0],
+            game.players[1],
+            game.variant as u8,
+            game.current_stage,
+            game.termination,
+            game.last_clock
+        );
+    }
+    println!("{} games", games.len());
+    Ok(())
+}
+
+async fn timeline(mongo: &Mongo, id: &str) -> Result<(), String> {
+    let game = find_game(mongo, id).await?;
+    println!(
+        "{} vs {}, variant {}, {}+{}",
+        game.players[0],
+        game.players[1],
+        game.variant as u8,
+        game.min.num_minutes(),
+        game.incr.num_seconds()
+    );
+    println!("hands: {} / {}", game.hands[0], game.hands[1]);
+    let replay = GameReplay::new(&game).map_err(|e| format!("{:?}", e))?;
+    for position in &replay.positions {
+        let clock = match position.move_time {
+            Some(time) => format!("{}ms left, {}ms spent", time.clock, time.spent),
+            None => String::new(),
+        };
+        let game_move = match position.game_move.as_str() {
+            "" => "start",
+            game_move => game_move,
+        };
+        println!(
+            "stage {} {} {} {}",
+            position.stage, game_move, position.sfen, clock
+        );
+    }
+    println!(
+        "{} ({}), result {}, last move {}",
+        game.termination, game.status as i32, game.result as u8, game.last_clock
+    );
+    Ok(())
+}
+
+async fn end(
+    mongo: &Mongo,
+    id: &str,
+    status: &str,
+    result: Option<&str>,
+) -> Result<(), String> {
+    // Game task of running server would overwrite unfinished game on next
+    // move, stuck games are ended here only while server is stopped.
+    let mut game = find_game(mongo, id).await?;
+    let status = status
+        .parse::<i64>()
+        .map_err(|_| format!("{} is not status", status))?;
+    let result = match result {
+        Some(result) => Some(
+            result
+                .parse::<i64>()
+                .map_err(|_| format!("{} is not result", result))?,
+        ),
+        None => None,
+    };
+    let (status, result) = GameStatus::admin_end(status, result)?;
+    game.set_status(status, result);
+    update_entire_game(&mongo.games, &game).await;
+    println!("{} ended: {}", game._id, game.termination);
+    Ok(())
+}
+
+async fn delete_anons(mongo: &Mongo, days: &str) -> Result<(), String> {
+    let days = days
+        .parse::<u32>()
+        .map_err(|_| format!("{} is not number of days", days))?;
+    let days = i64::from(days);
+    let before = DateTime::now().timestamp_millis() - days * 24 * 60 * 60 * 1000;
+    let deleted = delete_unused_anons(mongo, DateTime::from_millis(before)).await;
+    println!("deleted {} anonymous players", deleted);
+    Ok(())
+}
+
+async fn role(
+    mongo: &Mongo,
+    username: &str,
+    role: &str,
+    grant: bool,
+) -> Result<(), String> {
+    let role: Role = bson::from_bson(bson::Bson::String(String::from(role)))
+        .map_err(|_| format!("{} is not admin or moderator", role))?;
+    let changed = change_role(mongo, ACTOR, username, role, grant, None)
+        .await
+        .ok_or_else(|| format!("{} is not registered player", username))?;
+    match changed {
+        true => println!("{} changed", username),
+        false => println!("{} already had this role", username),
+    }
+    Ok(())
+}
+
+async fn stats(mongo: &Mongo) -> Result<(), String> {
+    let finished = doc! {"status": GameStatus::finished_filter()};
+    let unfinished = doc! {"status": GameStatus::unfinished_filter()};
+    let counts = [
+        ("players", mongo.players.count_documents(doc! {}).await),
+        (
+            "registered",
+            mongo.players.count_documents(doc! {"reg": true}).await,
+        ),
+        ("games", mongo.games.count_documents(doc! {}).await),
+        (
+            "finished games",
+            mongo.games.count_documents(finished).await,
+        ),
+        (
+            "unfinished games",
+            mongo.games.count_documents(unfinished).await,
+        ),
+        (
+            "pending challenges",
+            mongo
+                .challenges
+                .count_documents(doc! {"status": "pending"})
+                .await,
+        ),
+        ("sanctions", mongo.sanctions.count_documents(doc! {}).await),
+    ];
+    for (name, count) in counts {
+        println!("{}: {}", name, count.map_err(|e| e.to_string())?);
+    }
+    let pipeline = vec![
+        doc! {"$group": {"_id": "$variant", "count": {"$sum": 1}}},
+        doc! {"$sort": {"_id": 1}},
+    ];
+    let variants: Vec<bson::Document> = mongo
+        .games
+        .aggregate(pipeline)
+        .await
+        .map_err(|e| e.to_string())?
+        .try_collect()
+        .await
+        .map_err(|e| e.to_string())?;
+    for variant in variants {
+        let id = variant.get("_id").cloned().unwrap_or_default();
+        let count = variant.get("count").cloned().unwrap_or_default();
+        println!("variant {}: {}", id, count);
+    }
+    Ok(())
+}
+
+async fn find_game(mongo: &Mongo, id: &str) -> Result<ShuuroGame, String> {
+    get_game_db(&mongo.games, &String::from(id))
+        .await
+        .ok_or_else(|| format!("game {} not found", id))
+}
@@ -0,0 +1,37 @@
+//! Apply pending database migrations, `--dry-run` only reports documents
+//! that would change. Uses same config as server.
+
+use lishuuro::{
+    config::Config,
+    database::{indexes::ensure_indexes, migrations::run_migrations, model::Mongo},
+};
+
+#[tokio::main]
+async fn main() {
+    dotenv::dotenv().ok();
+    let dry_run = std::env::args().any(|arg| arg == "--dry-run");
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let mongo = Mongo::new(&config.mongo, &config.mongo_db).await;
+    if !dry_run {
+        if let Err(e) = ensure_indexes(&mongo).await {
+            eprintln!("failed to create indexes: {}", e);
+            std::process::exit(1);
+        }
+    }
+    match run_migrations(&mongo, dry_run).await {
+        Ok(reports) => {
+            println!("{}", serde_json::to_string_pretty(&reports).unwrap());
+        }
+        Err(e) => {
+            // Migrations before it are applied and recorded.
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! Replay stored games and report inconsistent ones.
+//!
+//! `--report <file>` writes JSON report, `--repair` overwrites derived fields
+//! (`sfen`, `status`, `result`, `termination`) of finished games.
+
+use lishuuro::{
+    config::Config,
+    database::{model::Mongo, verify::verify_games},
+};
+
+#[tokio::main]
+async fn main() {
+    dotenv::dotenv().ok();
+    let args: Vec<String> = std::env::args().collect();
+    let repair = args.iter().any(|arg| arg == "--repair");
+    let report_file = args
+        .iter()
+        .position(|arg| arg == "--report")
+        .and_then(|index| args.get(index + 1));
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let mongo = Mongo::new(&config.mongo, &config.mongo_db).await;
+    let report = match verify_games(&mongo.games, repair).await {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    println!(
+        "checked {} games, {} with issues, {} repaired",
+        report.checked,
+        report.games.len(),
+        report.repaired
+    );
+    for game in &report.games {
+        println!("{}: {} issues", game.id, game.issues.len());
+    }
+    if let Some(file) = report_file {
+        let json = serde_json::to_string_pretty(&report).unwrap();
+        if let Err(e) = std::fs::write(file, json) {
+            eprintln!("{}: {}", file, e);
+            std::process::exit(1);
+        }
+    }
+}
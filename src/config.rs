@@ -0,0 +1,364 @@
+use std::{env, fmt, fs, net::SocketAddr, path::Path};
+
+use serde::Deserialize;
+use url::Url;
+
+use crate::database::redis::ANON_SESSION_DAYS;
+
+/// Config file that is read when `LISHUURO_CONFIG` is not set.
+pub const DEFAULT_CONFIG_FILE: &str = "lishuuro.toml";
+
+/// Server configuration, validated at startup.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub mongo: String,
+    pub mongo_db: String,
+    pub redis: String,
+    pub login_state: String,
+    pub prod: bool,
+    pub bind: SocketAddr,
+    /// Public address of this server, used for lichess callback.
+    pub server_url: String,
+    /// Address of frontend, allowed by CORS.
+    pub frontend_url: String,
+    /// Shown in page titles, host of `server_url` by default.
+    pub site_name: String,
+    pub pockets: PocketsConfig,
+    /// Registered players that get admin role at startup.
+    pub admins: Vec<String>,
+    /// Run pending database migrations at startup.
+    pub migrate: bool,
+    /// Anonymous players older than this are removed, 0 (default) disables
+    /// cleanup. Must be longer than session of anonymous player.
+    pub anon_retention_days: u32,
+    /// Replace names of removed anonymous players in their games, otherwise
+    /// players with games are kept.
+    pub anonymize_anons: bool,
+}
+
+/// Pockets used by AI, comma separated.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PocketsConfig {
+    pub mini: String,
+    pub mini_fairy: String,
+    pub standard: String,
+    pub standard_fairy: String,
+    pub large: String,
+    pub large_fairy: String,
+}
+
+impl Default for PocketsConfig {
+    fn default() -> Self {
+        Self {
+            mini: String::from("qnn"),
+            mini_fairy: String::from("qnn"),
+            standard: String::from("qnn"),
+            standard_fairy: String::from("qnn"),
+            large: String::from("qnnr"),
+            large_fairy: String::from("qnnrp"),
+        }
+    }
+}
+
+/// Unvalidated values from config file and environment.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RawConfig {
+    pub mongo: Option<String>,
+    pub mongo_db: Option<String>,
+    pub redis: Option<String>,
+    pub login_state: Option<String>,
+    pub prod: Option<bool>,
+    pub vue: Option<bool>,
+    pub bind: Option<String>,
+    pub server_url: Option<String>,
+    pub frontend_url: Option<String>,
+    pub site_name: Option<String>,
+    pub pockets: Option<PocketsConfig>,
+    pub admins: Option<Vec<String>>,
+    pub migrate: Option<bool>,
+    pub anon_retention_days: Option<u32>,
+    pub anonymize_anons: Option<bool>,
+    #[serde(skip)]
+    errors: Vec<String>,
+}
+
+impl Config {
+    /// Load config file (if any), then override it with environment.
+    pub fn load() -> Result<Self, ConfigError> {
+        let path = env::var("LISHUURO_CONFIG").ok();
+        let mut raw = match path {
+            Some(ref path) => RawConfig::from_file(Path::new(path))?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                RawConfig::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => RawConfig::default(),
+        };
+        raw.merge_env();
+        raw.validate()
+    }
+}
+
+impl RawConfig {
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let file = path.display().to_string();
+        let content = fs::read_to_string(path).map_err(|e| ConfigError {
+            errors: vec![format!("{}: {}", file, e)],
+        })?;
+        toml::from_str(&content).map_err(|e| ConfigError {
+            errors: vec![format!("{}: {}", file, e)],
+        })
+    }
+
+    /// Environment variables take precedence over config file.
+    pub fn merge_env(&mut self) {
+        let vars = [
+            ("MONGO", &mut self.mongo),
+            ("MONGO_DB", &mut self.mongo_db),
+            ("REDIS", &mut self.redis),
+            ("LOGIN_STATE", &mut self.login_state),
+            ("BIND", &mut self.bind),
+            ("SERVER_URL", &mut self.server_url),
+            ("FRONTEND_URL", &mut self.frontend_url),
+            ("SITE_NAME", &mut self.site_name),
+        ];
+        for (name, value) in vars {
+            if let Ok(var) = env::var(name) {
+                *value = Some(var);
+            }
+        }
+        let vars = [
+            ("PROD", &mut self.prod),
+            ("VUE", &mut self.vue),
+            ("MIGRATE", &mut self.migrate),
+            ("ANONYMIZE_ANONS", &mut self.anonymize_anons),
+        ];
+        for (name, value) in vars {
+            if let Ok(var) = env::var(name) {
+                match var.parse::<bool>() {
+                    Ok(var) => *value = Some(var),
+                    Err(_) => self
+                        .errors
+                        .push(format!("{}: '{}' is not true or false", name, var)),
+                }
+            }
+        }
+        if let Ok(var) = env::var("ANON_RETENTION_DAYS") {
+            match var.parse::<u32>() {
+                Ok(var) => self.anon_retention_days = Some(var),
+                Err(_) => self.errors.push(format!(
+                    "ANON_RETENTION_DAYS: '{}' is not number of days",
+                    var
+                )),
+            }
+        }
+        if let Ok(admins) = env::var("ADMINS") {
+            let admins = admins
+                .split(',')
+                .map(|admin| admin.trim().to_string())
+                .filter(|admin| !admin.is_empty());
+            self.admins = Some(admins.collect());
+        }
+        let pockets = self.pockets.get_or_insert_with(PocketsConfig::default);
+        let vars = [
+            ("MINI", &mut pockets.mini),
+            ("MINI_FAIRY", &mut pockets.mini_fairy),
+            ("STANDARD", &mut pockets.standard),
+            ("STANDARD_FAIRY", &mut pockets.standard_fairy),
+            ("LARGE", &mut pockets.large),
+            ("LARGE_FAIRY", &mut pockets.large_fairy),
+        ];
+        for (name, value) in vars {
+            if let Ok(var) = env::var(name) {
+                *value = var;
+            }
+        }
+    }
+
+    /// Check all values, every problem is reported at once.
+    pub fn validate(self) -> Result<Config, ConfigError> {
+        let mut errors = self.errors;
+
+        let mongo = required("mongo", self.mongo, &mut errors);
+        if !mongo.is_empty()
+            && !mongo.starts_with("mongodb://")
+            && !mongo.starts_with("mongodb+srv://")
+        {
+            errors.push(format!("mongo: '{}' is not mongodb:// address", mongo));
+        }
+        let redis = required("redis", self.redis, &mut errors);
+        if !redis.is_empty()
+            && !redis.starts_with("redis://")
+            && !redis.starts_with("rediss://")
+        {
+            errors.push(format!("redis: '{}' is not redis:// address", redis));
+        }
+        let login_state = required("login_state", self.login_state, &mut errors);
+        let prod = self.prod.unwrap_or(false);
+        let vue = self.vue.unwrap_or(true);
+
+        let bind = self.bind.unwrap_or(String::from("0.0.0.0:3000"));
+        let bind = bind.parse::<SocketAddr>().unwrap_or_else(|_| {
+            errors.push(format!("bind: '{}' is not socket address", bind));
+            SocketAddr::from(([0, 0, 0, 0], 3000))
+        });
+
+        let default_server = if prod {
+            "https://lishuuro.org"
+        } else {
+            "http://localhost:3000"
+        };
+        let server_url = url(
+            "server_url",
+            self.server_url.unwrap_or(String::from(default_server)),
+            &mut errors,
+        );
+        let frontend_url = match self.frontend_url {
+            Some(frontend_url) => url("frontend_url", frontend_url, &mut errors),
+            None if !prod && vue => String::from("http://localhost:5173"),
+            None => server_url.clone(),
+        };
+        let anon_retention_days = self.anon_retention_days.unwrap_or(0);
+        if (1..=ANON_SESSION_DAYS).contains(&anon_retention_days) {
+            errors.push(format!(
+                "anon_retention_days: must be 0 or more than {} days, players \
+                 with live sessions would be removed",
+                ANON_SESSION_DAYS
+            ));
+        }
+        let site_name = match self.site_name {
+            Some(site_name) => site_name,
+            None => Url::parse(&server_url)
+                .ok()
+                .and_then(|url| url.host_str().map(String::from))
+                .unwrap_or_default(),
+        };
+
+        if !errors.is_empty() {
+            return Err(ConfigError { errors });
+        }
+        Ok(Config {
+            mongo,
+            mongo_db: self.mongo_db.unwrap_or(String::from("lishuuro")),
+            redis,
+            login_state,
+            prod,
+            bind,
+            server_url,
+            frontend_url,
+            site_name,
+            pockets: self.pockets.unwrap_or_default(),
+            admins: self.admins.unwrap_or_default(),
+            migrate: self.migrate.unwrap_or(true),
+            anon_retention_days,
+            anonymize_anons: self.anonymize_anons.unwrap_or(false),
+        })
+    }
+}
+
+fn required(key: &str, value: Option<String>, errors: &mut Vec<String>) -> String {
+    match value {
+        Some(value) if !value.is_empty() => value,
+        _ => {
+            errors.push(format!(
+                "{}: missing, set {} or add it to config file",
+                key,
+                key.to_uppercase()
+            ));
+            String::new()
+        }
+    }
+}
+
+fn url(key: &str, value: String, errors: &mut Vec<String>) -> String {
+    match Url::parse(&value) {
+        Ok(_) => value.trim_end_matches('/').to_string(),
+        Err(e) => {
+            errors.push(format!("{}: '{}' is not valid url ({})", key, value, e));
+            value
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "invalid configuration:")?;
+        for error in &self.errors {
+            writeln!(f, "  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw() -> RawConfig {
+        let mut raw = RawConfig::default();
+        raw.mongo = Some(String::from("mongodb://localhost:27017"));
+        raw.redis = Some(String::from("redis://localhost:6379"));
+        raw.login_state = Some(String::from("state"));
+        raw
+    }
+
+    #[test]
+    fn all_errors_are_reported() {
+        let mut raw = RawConfig::default();
+        raw.bind = Some(String::from("localhost"));
+        raw.server_url = Some(String::from("lishuuro"));
+        let err = raw.validate().unwrap_err();
+        assert_eq!(err.errors.len(), 5);
+    }
+
+    #[test]
+    fn urls_follow_prod_and_vue() {
+        let config = raw().validate().unwrap();
+        assert_eq!(config.server_url, "http://localhost:3000");
+        assert_eq!(config.frontend_url, "http://localhost:5173");
+        assert_eq!(config.bind, "0.0.0.0:3000".parse().unwrap());
+        assert_eq!(config.site_name, "localhost");
+
+        let mut prod = raw();
+        prod.prod = Some(true);
+        prod.server_url = Some(String::from("https://example.org/"));
+        let config = prod.validate().unwrap();
+        assert_eq!(config.server_url, "https://example.org");
+        assert_eq!(config.frontend_url, "https://example.org");
+        assert_eq!(config.site_name, "example.org");
+    }
+
+    #[test]
+    fn anon_retention_outlives_sessions() {
+        for days in [1, ANON_SESSION_DAYS] {
+            let mut raw = raw();
+            raw.anon_retention_days = Some(days);
+            assert_eq!(raw.validate().unwrap_err().errors.len(), 1);
+        }
+        for days in [0, ANON_SESSION_DAYS + 1] {
+            let mut raw = raw();
+            raw.anon_retention_days = Some(days);
+            assert_eq!(raw.validate().unwrap().anon_retention_days, days);
+        }
+    }
+
+    #[test]
+    fn file_rejects_unknown_keys() {
+        let raw = toml::from_str::<RawConfig>("mongo = \"mongodb://a\"\nport = 3\n");
+        assert!(raw.is_err());
+        let raw = toml::from_str::<RawConfig>(
+            "prod = true\n[pockets]\nmini = \"qr\"\n",
+        )
+        .unwrap();
+        assert_eq!(raw.prod, Some(true));
+        assert_eq!(raw.pockets.unwrap().large, "qnnr");
+    }
+}
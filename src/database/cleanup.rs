@@ -0,0 +1,198 @@
+use std::sync::Arc;
+
+use bson::{doc, DateTime, Document};
+use chrono::Duration;
+use futures::TryStreamExt;
+use mongodb::options::UpdateOptions;
+use serde::{Deserialize, Serialize};
+use typeshare::typeshare;
+
+use super::{
+    model::{GameStatus, Mongo},
+    redis::RedisCli,
+    Database,
+};
+
+/// Name that replaces anonymized players in their games.
+pub const ANONYMIZED: &str = "Anon-deleted";
+/// How often expired anonymous players are removed.
+const CLEANUP_INTERVAL_HOURS: i64 = 24;
+/// Last report is kept until two cleanups are missed.
+const REPORT_TTL: usize = 2 * CLEANUP_INTERVAL_HOURS as usize * 60 * 60;
+const REPORT_KEY: &str = "cleanup:last";
+
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct CleanupReport {
+    /// Players without games.
+    #[typeshare(serialized_as = "u32")]
+    pub deleted: u64,
+    /// Players whose names were replaced in their games.
+    #[typeshare(serialized_as = "u32")]
+    pub anonymized: u64,
+    /// Players with games, kept because anonymizing is off or because one
+    /// of their games is not finished.
+    #[typeshare(serialized_as = "u32")]
+    pub kept: u64,
+}
+
+/// Expired anonymous players split by their games.
+#[derive(Default)]
+struct ExpiredAnons {
+    unused: Vec<String>,
+    /// Every game is finished or aborted.
+    finished: Vec<String>,
+    /// Server may still run their game.
+    live: Vec<String>,
+}
+
+/// Anonymous players created before `before` that never played a game.
+pub async fn unused_anons(mongo: &Mongo, before: DateTime) -> Vec<String> {
+    expired_anons(mongo, before).await.unused
+}
+
+/// Returns number of deleted players.
+pub async fn delete_unused_anons(mongo: &Mongo, before: DateTime) -> u64 {
+    let players = unused_anons(mongo, before).await;
+    delete_players(mongo, players).await
+}
+
+/// Delete anonymous players created before `before` without games. Names
+/// of others are replaced in their games if `anonymize` is set, players with
+/// unfinished games are always kept.
+pub async fn cleanup_anons(
+    mongo: &Mongo,
+    before: DateTime,
+    anonymize: bool,
+) -> CleanupReport {
+    let expired = expired_anons(mongo, before).await;
+    let mut report = CleanupReport {
+        deleted: delete_players(mongo, expired.unused).await,
+        kept: expired.live.len() as u64,
+        ..Default::default()
+    };
+    if !anonymize {
+        report.kept += expired.finished.len() as u64;
+        return report;
+    }
+    let mut anonymized = vec![];
+    for player in expired.finished {
+        if anonymize_games(mongo, &player).await {
+            anonymized.push(player);
+        }
+    }
+    report.anonymized = delete_players(mongo, anonymized).await;
+    report
+}
+
+/// Report of last cleanup, `None` if it didn't run for two days.
+pub async fn last_cleanup(redis: &RedisCli) -> Option<CleanupReport> {
+    redis.clone().get_cached(REPORT_KEY).await
+}
+
+/// Runs cleanup once a day, `retention_days` 0 disables it.
+pub fn cleanup_task(db: Arc<Database>, retention_days: u32, anonymize: bool) {
+    if retention_days == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        loop {
+            let retention = Duration::days(retention_days as i64);
+            let before = db.clock.bson_now().timestamp_millis()
+                - retention.num_milliseconds();
+            let before = DateTime::from_millis(before);
+            let report = cleanup_anons(&db.mongo, before, anonymize).await;
+            println!(
+                "anonymous players: {} deleted, {} anonymized, {} kept",
+                report.deleted, report.anonymized, report.kept
+            );
+            let mut redis = db.redis.clone();
+            redis.set_cached(REPORT_KEY, &report, REPORT_TTL).await;
+            db.clock.sleep(Duration::hours(CLEANUP_INTERVAL_HOURS)).await;
+        }
+    });
+}
+
+async fn expired_anons(mongo: &Mongo, before: DateTime) -> ExpiredAnons {
+    let unfinished = doc! {"status": GameStatus::unfinished_filter()};
+    let pipeline = vec![
+        doc! {"$match": {"reg": false, "created_at": {"$lt": before}}},
+        any_game(mongo, "played", doc! {}),
+        any_game(mongo, "live", unfinished),
+        doc! {"$project": {
+            "played": {"$gt": [{"$size": "$played"}, 0]},
+            "live": {"$gt": [{"$size": "$live"}, 0]}
+        }},
+    ];
+    let mut expired = ExpiredAnons::default();
+    let Ok(res) = mongo.players.aggregate(pipeline).await else {
+        return expired;
+    };
+    let docs: Vec<Document> = res.try_collect().await.unwrap_or_else(|_| vec![]);
+    for doc in docs {
+        let Ok(id) = doc.get_str("_id") else {
+            continue;
+        };
+        let played = doc.get_bool("played").unwrap_or(false);
+        let live = doc.get_bool("live").unwrap_or(false);
+        let list = match (played, live) {
+            (_, true) => &mut expired.live,
+            (true, false) => &mut expired.finished,
+            (false, false) => &mut expired.unused,
+        };
+        list.push(String::from(id));
+    }
+    expired
+}
+
+/// Adds field `name` with one game of player that matches `filter`.
+fn any_game(mongo: &Mongo, name: &str, filter: Document) -> Document {
+    doc! {"$lookup": {
+        "from": mongo.games.name(),
+        "localField": "_id",
+        "foreignField": "players",
+        "pipeline": [{"$match": filter}, {"$limit": 1}, {"$project": {"_id": 1}}],
+        "as": name
+    }}
+}
+
+async fn anonymize_games(mongo: &Mongo, player: &str) -> bool {
+    let filter = doc! {"players": player};
+    let update = doc! {"$set": {"players.$[player]": ANONYMIZED}};
+    let options = UpdateOptions::builder()
+        .array_filters(vec![doc! {"player": player}])
+        .build();
+    mongo
+        .games
+        .update_many(filter, update)
+        .with_options(options)
+        .await
+        .is_ok()
+}
+
+/// Deletes players and everything that refers to them by name.
+async fn delete_players(mongo: &Mongo, players: Vec<String>) -> u64 {
+    if players.is_empty() {
+        return 0;
+    }
+    // `reg` is checked again in case player registered meanwhile.
+    let filter = doc! {"_id": {"$in": players.clone()}, "reg": false};
+    let deleted = match mongo.players.delete_many(filter).await {
+        Ok(res) => res.deleted_count,
+        Err(_) => return 0,
+    };
+    let names = doc! {"$in": players};
+    let notifications = doc! {"username": names.clone()};
+    let _ = mongo.notifications.delete_many(notifications).await;
+    let follows = doc! {"$or": [
+        {"follower": names.clone()},
+        {"followed": names.clone()}
+    ]};
+    let _ = mongo.follows.delete_many(follows).await;
+    let challenges = doc! {"$or": [
+        {"challenger": names.clone()},
+        {"target": names}
+    ]};
+    let _ = mongo.challenges.delete_many(challenges).await;
+    deleted
+}
@@ -1,2 +1,3 @@
 pub mod queries;
 pub mod time_control;
+pub mod time_source;
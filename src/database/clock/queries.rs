@@ -1,14 +1,20 @@
 use std::collections::HashMap;
 
 use base64::{prelude::BASE64_STANDARD, Engine};
-use bson::doc;
+use bson::{doc, oid::ObjectId};
 use futures::TryStreamExt;
-use mongodb::{options::FindOptions, Collection};
+use mongodb::{
+    options::{FindOptions, ReturnDocument},
+    Collection,
+};
 use rand::Rng;
 
 use crate::{
     database::{
-        model::{Player, ShuuroGame},
+        model::{
+            AuditEntry, Challenge, ChallengeStatus, Follow, GameStatus,
+            Notification, Player, Role, Sanction, SanctionKind, ShuuroGame,
+        },
         redis::UserSession,
     },
     lichess::login_helpers::base64_encode,
@@ -36,6 +42,8 @@ pub async fn create_player(db: &Collection<Player>) -> String {
             _id: String::from(&username),
             reg: false,
             created_at: bson::DateTime::now(),
+            roles: vec![],
+            blocked: vec![],
         };
         let res = db.insert_one(&player).await;
         // Player is added, therefore it's new.
@@ -79,6 +87,329 @@ pub async fn player_exist(
     }
 }
 
+/// Add or remove role for registered player.
+///
+/// Returns `None` if player is not found, otherwise whether roles changed.
+pub async fn set_role(
+    db: &Collection<Player>,
+    username: &str,
+    role: Role,
+    grant: bool,
+) -> Option<bool> {
+    let role = bson::to_bson(&role).ok()?;
+    let update = if grant {
+        doc! {"$addToSet": {"roles": role}}
+    } else {
+        doc! {"$pull": {"roles": role}}
+    };
+    let filter = doc! {"_id": String::from(username), "reg": true};
+    let res = db.update_one(filter, update).await.ok()?;
+    if res.matched_count == 0 {
+        return None;
+    }
+    Some(res.modified_count > 0)
+}
+
+/// Returns `None` if player is not found.
+pub async fn set_blocked(
+    db: &Collection<Player>,
+    username: &str,
+    other: &str,
+    block: bool,
+) -> Option<bool> {
+    let update = if block {
+        doc! {"$addToSet": {"blocked": String::from(other)}}
+    } else {
+        doc! {"$pull": {"blocked": String::from(other)}}
+    };
+    let filter = doc! {"_id": String::from(username)};
+    let res = db.update_one(filter, update).await.ok()?;
+    if res.matched_count == 0 {
+        return None;
+    }
+    Some(res.modified_count > 0)
+}
+
+/// Check if `username` blocked `other`.
+pub async fn is_blocked(
+    db: &Collection<Player>,
+    username: &str,
+    other: &str,
+) -> bool {
+    let filter = doc! {
+        "_id": String::from(username),
+        "blocked": String::from(other)
+    };
+    matches!(db.count_documents(filter).await, Ok(count) if count > 0)
+}
+
+/// Players from `players` that blocked `other`.
+pub async fn blocked_by(
+    db: &Collection<Player>,
+    other: &str,
+    players: Vec<String>,
+) -> Vec<String> {
+    let filter = doc! {"_id": {"$in": players}, "blocked": String::from(other)};
+    let Ok(res) = db.find(filter).await else {
+        return vec![];
+    };
+    let players: Vec<Player> = res.try_collect().await.unwrap_or_else(|_| vec![]);
+    players.into_iter().map(|player| player._id).collect()
+}
+
+pub async fn add_audit_entry(db: &Collection<AuditEntry>, entry: &AuditEntry) {
+    if let Err(_res) = db.insert_one(entry).await {}
+}
+
+/// Latest entries first.
+pub async fn get_audit_log(
+    db: &Collection<AuditEntry>,
+    target: Option<&str>,
+    limit: i64,
+) -> Vec<AuditEntry> {
+    let filter = match target {
+        Some(target) => doc! {"target": String::from(target)},
+        None => doc! {},
+    };
+    let options = FindOptions::builder()
+        .sort(doc! {"created_at": -1})
+        .limit(Some(limit))
+        .build();
+    let Ok(res) = db.find(filter).with_options(options).await else {
+        return vec![];
+    };
+    res.try_collect().await.unwrap_or_else(|_| vec![])
+}
+
+fn active_sanction_filter(username: &str, now: bson::DateTime) -> bson::Document {
+    doc! {
+        "username": String::from(username),
+        "$or": [{"expires_at": null}, {"expires_at": {"$gt": now}}]
+    }
+}
+
+pub async fn add_sanction(db: &Collection<Sanction>, sanction: &Sanction) {
+    if let Err(_res) = db.insert_one(sanction).await {}
+}
+
+/// Expire all active sanctions of given kind. Returns how many were active.
+pub async fn lift_sanction(
+    db: &Collection<Sanction>,
+    username: &str,
+    kind: SanctionKind,
+    now: bson::DateTime,
+) -> u64 {
+    let Ok(kind) = bson::to_bson(&kind) else {
+        return 0;
+    };
+    let mut filter = active_sanction_filter(username, now);
+    filter.insert("kind", kind);
+    let update = doc! {"$set": {"expires_at": now}};
+    match db.update_many(filter, update).await {
+        Ok(res) => res.modified_count,
+        Err(_) => 0,
+    }
+}
+
+pub async fn active_sanctions(
+    db: &Collection<Sanction>,
+    username: &str,
+    now: bson::DateTime,
+) -> Vec<Sanction> {
+    let Ok(res) = db.find(active_sanction_filter(username, now)).await else {
+        return vec![];
+    };
+    res.try_collect().await.unwrap_or_else(|_| vec![])
+}
+
+pub async fn is_sanctioned(
+    db: &Collection<Sanction>,
+    username: &str,
+    kind: SanctionKind,
+    now: bson::DateTime,
+) -> bool {
+    active_sanctions(db, username, now)
+        .await
+        .iter()
+        .any(|sanction| sanction.kind == kind)
+}
+
+/// All sanctions for player, latest first.
+pub async fn get_sanctions(
+    db: &Collection<Sanction>,
+    username: &str,
+) -> Vec<Sanction> {
+    let options = FindOptions::builder().sort(doc! {"created_at": -1}).build();
+    let filter = doc! {"username": String::from(username)};
+    let Ok(res) = db.find(filter).with_options(options).await else {
+        return vec![];
+    };
+    res.try_collect().await.unwrap_or_else(|_| vec![])
+}
+
+pub async fn add_challenge(db: &Collection<Challenge>, challenge: &Challenge) {
+    if let Err(_res) = db.insert_one(challenge).await {}
+}
+
+pub async fn get_challenge(
+    db: &Collection<Challenge>,
+    id: &str,
+) -> Option<Challenge> {
+    db.find_one(doc! {"_id": String::from(id)}).await.ok()?
+}
+
+/// Close pending challenge, returns it only if it was still pending.
+pub async fn close_challenge(
+    db: &Collection<Challenge>,
+    id: &str,
+    status: ChallengeStatus,
+    reason: Option<String>,
+    game: Option<String>,
+) -> Option<Challenge> {
+    let status = bson::to_bson(&status).ok()?;
+    let filter = doc! {"_id": String::from(id), "status": "pending"};
+    let update = doc! {"$set": {"status": status, "reason": reason, "game": game}};
+    db.find_one_and_update(filter, update)
+        .return_document(ReturnDocument::After)
+        .await
+        .ok()?
+}
+
+/// Pending challenges that player sent or received, oldest first.
+pub async fn pending_challenges(
+    db: &Collection<Challenge>,
+    username: &str,
+    now: bson::DateTime,
+) -> Vec<Challenge> {
+    let options = FindOptions::builder().sort(doc! {"created_at": 1}).build();
+    let filter = doc! {
+        "$or": [
+            {"target": String::from(username)},
+            {"challenger": String::from(username)}
+        ],
+        "status": "pending",
+        "expires_at": {"$gt": now}
+    };
+    let Ok(res) = db.find(filter).with_options(options).await else {
+        return vec![];
+    };
+    res.try_collect().await.unwrap_or_else(|_| vec![])
+}
+
+pub async fn add_notification(
+    db: &Collection<Notification>,
+    notification: &Notification,
+) {
+    if let Err(_res) = db.insert_one(notification).await {}
+}
+
+/// Latest notifications for player.
+pub async fn get_notifications(
+    db: &Collection<Notification>,
+    username: &str,
+    unread_only: bool,
+    limit: i64,
+) -> Vec<Notification> {
+    let mut filter = doc! {"username": String::from(username)};
+    if unread_only {
+        filter.insert("read", false);
+    }
+    let options = FindOptions::builder()
+        .sort(doc! {"created_at": -1})
+        .limit(Some(limit))
+        .build();
+    let Ok(res) = db.find(filter).with_options(options).await else {
+        return vec![];
+    };
+    res.try_collect().await.unwrap_or_else(|_| vec![])
+}
+
+/// Mark given notifications as read, or all of them if `ids` is missing or
+/// empty.
+pub async fn mark_notifications_read(
+    db: &Collection<Notification>,
+    username: &str,
+    ids: Option<Vec<ObjectId>>,
+) -> u64 {
+    let mut filter = doc! {"username": String::from(username), "read": false};
+    if let Some(ids) = ids.filter(|ids| !ids.is_empty()) {
+        filter.insert("_id", doc! {"$in": ids});
+    }
+    let update = doc! {"$set": {"read": true}};
+    match db.update_many(filter, update).await {
+        Ok(res) => res.modified_count,
+        Err(_) => 0,
+    }
+}
+
+pub async fn unread_notifications(
+    db: &Collection<Notification>,
+    username: &str,
+) -> u64 {
+    let filter = doc! {"username": String::from(username), "read": false};
+    db.count_documents(filter).await.unwrap_or(0)
+}
+
+/// Returns `false` if player was already followed.
+pub async fn follow(
+    db: &Collection<Follow>,
+    follower: &str,
+    followed: &str,
+    now: bson::DateTime,
+) -> bool {
+    let filter = doc! {
+        "follower": String::from(follower),
+        "followed": String::from(followed)
+    };
+    let update = doc! {
+        "$setOnInsert": {
+            "_id": ObjectId::new(),
+            "follower": String::from(follower),
+            "followed": String::from(followed),
+            "created_at": now
+        }
+    };
+    match db.update_one(filter, update).upsert(true).await {
+        Ok(res) => res.upserted_id.is_some(),
+        Err(_) => false,
+    }
+}
+
+pub async fn unfollow(
+    db: &Collection<Follow>,
+    follower: &str,
+    followed: &str,
+) -> bool {
+    let filter = doc! {
+        "follower": String::from(follower),
+        "followed": String::from(followed)
+    };
+    match db.delete_one(filter).await {
+        Ok(res) => res.deleted_count > 0,
+        Err(_) => false,
+    }
+}
+
+/// Players that `username` follows.
+pub async fn get_following(db: &Collection<Follow>, username: &str) -> Vec<String> {
+    let filter = doc! {"follower": String::from(username)};
+    let Ok(res) = db.find(filter).await else {
+        return vec![];
+    };
+    let follows: Vec<Follow> = res.try_collect().await.unwrap_or_else(|_| vec![]);
+    follows.into_iter().map(|follow| follow.followed).collect()
+}
+
+/// Players that follow `username`.
+pub async fn get_followers(db: &Collection<Follow>, username: &str) -> Vec<String> {
+    let filter = doc! {"followed": String::from(username)};
+    let Ok(res) = db.find(filter).await else {
+        return vec![];
+    };
+    let follows: Vec<Follow> = res.try_collect().await.unwrap_or_else(|_| vec![]);
+    follows.into_iter().map(|follow| follow.follower).collect()
+}
+
 pub async fn game_id(db: &Collection<ShuuroGame>) -> String {
     loop {
         let id = random_game_id();
@@ -140,7 +471,10 @@ pub async fn get_player_games(
         .skip(Some(page * 5))
         .limit(Some(5))
         .build();
-    let filter = doc! {"players": {"$in": [username] }, "status":{"$gt": 0} };
+    let filter = doc! {
+        "players": {"$in": [username]},
+        "status": GameStatus::finished_filter()
+    };
     let q = db
         .clone_with_type::<ShuuroGame>()
         .find(filter)
@@ -159,7 +493,7 @@ pub async fn get_player_games(
 }
 
 pub async fn unfinished(db: &Collection<ShuuroGame>) -> HashMap<String, ShuuroGame> {
-    let filter = doc! {"status" : {"$lt": 0}};
+    let filter = doc! {"status": GameStatus::unfinished_filter()};
     let mut hm = HashMap::new();
     let c = db.find(filter);
     if let Ok(c) = c.await {
@@ -1,10 +1,12 @@
-use chrono::{DateTime, Duration, FixedOffset, TimeDelta, Utc};
+use chrono::{DateTime, Duration, FixedOffset, TimeDelta};
 use serde::{Deserialize, Serialize};
 use shuuro::Color;
 use typeshare::typeshare;
 
 use crate::database::serde_helpers::*;
 
+use super::time_source::{system_clock, Clock, TimeSource};
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[typeshare]
 pub struct TimeControl {
@@ -18,35 +20,63 @@ pub struct TimeControl {
     pub stage: u8,
     #[serde(skip)]
     pub incr: i64,
+    #[serde(skip, default = "system_clock")]
+    pub clock: Clock,
+}
+
+/// Clock of player after move and time spent on move, in milliseconds.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[typeshare]
+pub struct MoveTime {
+    #[typeshare(serialized_as = "u32")]
+    pub clock: u64,
+    #[typeshare(serialized_as = "u32")]
+    pub spent: u64,
 }
 
 impl TimeControl {
     /// Create new time control.
     pub fn new(time: i64, incr: i64) -> Self {
+        Self::with_clock(time, incr, system_clock())
+    }
+
+    /// Create new time control with custom time source.
+    pub fn with_clock(time: i64, incr: i64, clock: Clock) -> Self {
         let duration = Duration::seconds(time * 60 + incr);
-        let last_click = Utc::now().into();
+        let last_click = clock.now();
 
         Self {
             clocks: [duration, duration],
             stage: 0,
             incr,
             last_click,
+            clock,
         }
     }
 
     pub fn update_stage(&mut self, stage: u8) {
         self.stage = stage;
-        self.last_click = Utc::now().into();
+        self.last_click = self.clock.now();
     }
 
     pub fn play(&mut self, color: usize) -> Option<[u64; 2]> {
+        self.timed_play(color).map(|(ms, _)| ms)
+    }
+
+    /// Same as `play`, also returns mover's clock and time spent on move.
+    pub fn timed_play(&mut self, color: usize) -> Option<([u64; 2], MoveTime)> {
+        let spent = self.elapsed();
         let duration = self.current_duration(color)?;
         self.update_last_click(color, duration);
         let ms = [
             self.clocks[0].num_milliseconds() as u64,
             self.clocks[1].num_milliseconds() as u64,
         ];
-        Some(ms)
+        let move_time = MoveTime {
+            clock: ms[color],
+            spent: spent.num_milliseconds() as u64,
+        };
+        Some((ms, move_time))
     }
 
     pub fn current_duration(&self, color: usize) -> Option<Duration> {
@@ -68,7 +98,7 @@ impl TimeControl {
                 self.clocks[color] = duration;
 
                 if self.stage < 3 {
-                    self.last_click = Utc::now().into();
+                    self.last_click = self.clock.now();
                 }
             }
             None => self.clocks[color] = Duration::seconds(0),
@@ -87,7 +117,128 @@ impl TimeControl {
     }
 
     fn elapsed(&self) -> Duration {
-        let now: DateTime<FixedOffset> = Utc::now().into();
-        now - self.last_click
+        self.clock.now() - self.last_click
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::clock::time_source::FakeClock;
+
+    fn time_control(minutes: i64, incr: i64) -> (TimeControl, FakeClock) {
+        let fake = FakeClock::default();
+        (TimeControl::with_clock(minutes, incr, fake.clock()), fake)
+    }
+
+    #[test]
+    fn selection_flags_both_players() {
+        let (tc, fake) = time_control(1, 0);
+        fake.advance(Duration::seconds(59));
+        assert!(tc.current_duration(0).is_some());
+        assert!(tc.current_duration(1).is_some());
+        fake.advance(Duration::seconds(2));
+        assert!(tc.current_duration(0).is_none());
+        assert!(tc.current_duration(1).is_none());
+    }
+
+    #[test]
+    fn select_adds_increment_only_to_confirming_player() {
+        let (mut tc, fake) = time_control(1, 2);
+        let started = tc.last_click;
+        fake.advance(Duration::seconds(10));
+        let clocks = tc.select(Color::White);
+        assert_eq!(clocks[0], Duration::seconds(54));
+        assert_eq!(clocks[1], Duration::seconds(62));
+        assert_eq!(tc.stage, 0);
+        // Other player is still thinking from the same starting point.
+        assert_eq!(tc.last_click, started);
+        fake.advance(Duration::seconds(5));
+        assert_eq!(tc.current_duration(1), Some(Duration::seconds(47)));
+    }
+
+    #[test]
+    fn select_does_not_revive_flagged_player() {
+        let (mut tc, fake) = time_control(1, 5);
+        fake.advance(Duration::seconds(70));
+        let clocks = tc.select(Color::Black);
+        assert_eq!(clocks[1], Duration::seconds(65));
+        assert!(tc.current_duration(1).is_none());
+    }
+
+    #[test]
+    fn placement_flags_side_to_move() {
+        let (mut tc, fake) = time_control(1, 2);
+        tc.update_stage(1);
+        fake.advance(Duration::seconds(30));
+        let ms = tc.play(0).unwrap();
+        assert_eq!(ms, [34_000, 62_000]);
+        assert_eq!(tc.last_click, fake.now());
+        fake.advance(Duration::seconds(63));
+        assert!(tc.current_duration(1).is_none());
+        assert!(tc.play(1).is_none());
+    }
+
+    #[test]
+    fn fight_flags_side_to_move() {
+        let (mut tc, fake) = time_control(3, 0);
+        tc.update_stage(2);
+        fake.advance(Duration::seconds(100));
+        assert_eq!(tc.play(0), Some([80_000, 180_000]));
+        fake.advance(Duration::seconds(181));
+        assert!(tc.play(1).is_none());
+        assert_eq!(tc.clocks[1], Duration::seconds(180));
+    }
+
+    #[test]
+    fn timed_play_records_spent_time() {
+        let (mut tc, fake) = time_control(1, 2);
+        tc.update_stage(2);
+        fake.advance(Duration::seconds(15));
+        let (_, move_time) = tc.timed_play(1).unwrap();
+        assert_eq!(
+            move_time,
+            MoveTime {
+                clock: 49_000,
+                spent: 15_000
+            }
+        );
+    }
+
+    #[test]
+    fn increment_is_not_added_during_selection() {
+        let (mut tc, fake) = time_control(1, 10);
+        fake.advance(Duration::seconds(20));
+        assert_eq!(tc.play(0), Some([70_000, 70_000]));
+    }
+
+    #[test]
+    fn increment_is_added_after_move() {
+        let (mut tc, fake) = time_control(1, 10);
+        tc.update_stage(2);
+        fake.advance(Duration::seconds(69));
+        assert_eq!(tc.play(0), Some([11_000, 70_000]));
+    }
+
+    #[test]
+    fn flag_after_full_second_over() {
+        let (mut tc, fake) = time_control(1, 0);
+        tc.update_stage(2);
+        fake.advance(Duration::seconds(60));
+        assert_eq!(tc.current_duration(0), Some(Duration::zero()));
+        fake.advance(Duration::milliseconds(500));
+        let over = Duration::milliseconds(-500);
+        assert_eq!(tc.current_duration(0), Some(over));
+        fake.advance(Duration::milliseconds(500));
+        assert!(tc.current_duration(0).is_none());
+        assert!(tc.play(0).is_none());
+    }
+
+    #[test]
+    fn set_to_zero_flags_player() {
+        let (mut tc, _) = time_control(1, 0);
+        tc.set_to_zero(Color::White);
+        assert_eq!(tc.clocks[0], Duration::zero());
+        assert!(tc.current_duration(1).is_some());
     }
 }
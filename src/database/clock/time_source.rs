@@ -0,0 +1,84 @@
+use std::{fmt::Debug, sync::Arc};
+
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use futures::future::BoxFuture;
+use tokio::sync::watch;
+
+/// Source of current time for clocks.
+pub trait TimeSource: Debug + Send + Sync {
+    fn now(&self) -> DateTime<FixedOffset>;
+
+    /// Resolves when `duration` passed on this source.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+
+    fn bson_now(&self) -> bson::DateTime {
+        bson::DateTime::from_millis(self.now().timestamp_millis())
+    }
+}
+
+pub type Clock = Arc<dyn TimeSource>;
+
+pub fn system_clock() -> Clock {
+    Arc::new(SystemClock)
+}
+
+/// Wall clock, used by server.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock;
+
+impl TimeSource for SystemClock {
+    fn now(&self) -> DateTime<FixedOffset> {
+        Utc::now().into()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        let duration = duration.to_std().unwrap_or_default();
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Clock that moves only when `advance` is called.
+#[derive(Debug, Clone)]
+pub struct FakeClock {
+    now: Arc<watch::Sender<DateTime<FixedOffset>>>,
+}
+
+impl FakeClock {
+    pub fn new(start: DateTime<FixedOffset>) -> Self {
+        let (now, _) = watch::channel(start);
+        Self { now: Arc::new(now) }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.send_modify(|now| *now += duration);
+    }
+
+    pub fn clock(&self) -> Clock {
+        Arc::new(self.clone())
+    }
+}
+
+impl Default for FakeClock {
+    fn default() -> Self {
+        Self::new(DateTime::parse_from_rfc3339("2024-01-01T00:00:00+00:00").unwrap())
+    }
+}
+
+impl TimeSource for FakeClock {
+    fn now(&self) -> DateTime<FixedOffset> {
+        *self.now.borrow()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        let mut now = self.now.subscribe();
+        let deadline = *now.borrow() + duration;
+        Box::pin(async move {
+            loop {
+                let reached = *now.borrow_and_update() >= deadline;
+                if reached || now.changed().await.is_err() {
+                    return;
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,226 @@
+use bson::{doc, Bson, DateTime, Document};
+use mongodb::Collection;
+use serde::{Deserialize, Serialize};
+use typeshare::typeshare;
+
+use super::{
+    model::{GameResult, GameStatus, ShuuroGame},
+    stats::{aggregate, finished_games, GameOutcome, Score},
+};
+
+/// Most games returned with crosstable.
+pub const CROSSTABLE_GAMES_LIMIT: usize = 50;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct CrosstableGame {
+    pub _id: String,
+    pub variant: u8,
+    pub status: GameStatus,
+    pub result: GameResult,
+    pub players: [String; 2],
+    /// Outcome for first player of crosstable.
+    pub outcome: GameOutcome,
+    #[typeshare(serialized_as = "String")]
+    pub last_clock: DateTime,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct VariantScore {
+    pub variant: u8,
+    pub score: Score,
+}
+
+/// Wins in a row, counted from latest game.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[typeshare]
+pub struct Streak {
+    pub username: String,
+    pub count: u32,
+}
+
+/// Record between two players, scores are for first player.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct Crosstable {
+    pub players: [String; 2],
+    pub score: Score,
+    pub variants: Vec<VariantScore>,
+    /// Latest games first.
+    pub games: Vec<CrosstableGame>,
+    pub streak: Option<Streak>,
+}
+
+pub async fn crosstable(
+    db: &Collection<ShuuroGame>,
+    player: &str,
+    opponent: &str,
+    limit: usize,
+) -> Crosstable {
+    let base = pair_games(player, opponent);
+    let mut pipeline = base.clone();
+    pipeline.extend([
+        doc! {"$group": {
+            "_id": "$variant",
+            "wins": count_outcome(GameOutcome::Win),
+            "losses": count_outcome(GameOutcome::Loss),
+            "draws": count_outcome(GameOutcome::Draw)
+        }},
+        doc! {"$sort": {"_id": 1}},
+    ]);
+    let rows = aggregate::<VariantRow>(db, pipeline).await;
+    let (score, variants) = collect_scores(rows);
+
+    let mut pipeline = base.clone();
+    pipeline.extend([
+        doc! {"$sort": {"last_clock": -1}},
+        doc! {"$limit": limit as i64},
+        doc! {"$project": {
+            "variant": 1,
+            "status": 1,
+            "result": 1,
+            "players": 1,
+            "outcome": 1,
+            "last_clock": 1
+        }},
+    ]);
+    let games = aggregate::<CrosstableGame>(db, pipeline).await;
+    let streak = match games.first() {
+        Some(latest) => {
+            let count = streak_count(db, base, latest.outcome).await;
+            streak([player, opponent], latest.outcome, count)
+        }
+        None => None,
+    };
+    Crosstable {
+        players: [player, opponent].map(String::from),
+        score,
+        variants,
+        games,
+        streak,
+    }
+}
+
+/// Finished games between two players, with outcome for `player`.
+fn pair_games(player: &str, opponent: &str) -> Vec<Document> {
+    let mut pipeline = finished_games(player);
+    pipeline.push(doc! {"$match": {"players": String::from(opponent)}});
+    pipeline
+}
+
+fn count_outcome(outcome: GameOutcome) -> Document {
+    let outcome = bson::to_bson(&outcome).unwrap_or_default();
+    doc! {"$sum": {"$cond": [{"$eq": ["$outcome", outcome]}, 1, 0]}}
+}
+
+/// Games since latest game with other outcome.
+async fn streak_count(
+    db: &Collection<ShuuroGame>,
+    base: Vec<Document>,
+    outcome: GameOutcome,
+) -> u32 {
+    let outcome = bson::to_bson(&outcome).unwrap_or_default();
+    let mut pipeline = base.clone();
+    pipeline.extend([
+        doc! {"$match": {"outcome": {"$ne": outcome}}},
+        doc! {"$sort": {"last_clock": -1}},
+        doc! {"$limit": 1},
+        doc! {"$project": {"last_clock": 1}},
+    ]);
+    let end = aggregate::<Document>(db, pipeline).await;
+    let mut pipeline = base;
+    if let Some(end) = end.first().and_then(|game| game.get("last_clock")) {
+        pipeline.push(doc! {"$match": {"last_clock": {"$gt": end.clone()}}});
+    }
+    pipeline.push(doc! {"$count": "count"});
+    let count = aggregate::<Document>(db, pipeline).await;
+    match count.first().map(|count| count.get("count")) {
+        Some(Some(Bson::Int32(count))) => *count as u32,
+        Some(Some(Bson::Int64(count))) => *count as u32,
+        _ => 0,
+    }
+}
+
+#[derive(Deserialize)]
+struct VariantRow {
+    _id: u8,
+    wins: u32,
+    losses: u32,
+    draws: u32,
+}
+
+fn collect_scores(rows: Vec<VariantRow>) -> (Score, Vec<VariantScore>) {
+    let mut total = Score::default();
+    let variants = rows
+        .into_iter()
+        .map(|row| {
+            let score = Score {
+                wins: row.wins,
+                losses: row.losses,
+                draws: row.draws,
+            };
+            total.add(GameOutcome::Win, score.wins);
+            total.add(GameOutcome::Loss, score.losses);
+            total.add(GameOutcome::Draw, score.draws);
+            VariantScore {
+                variant: row._id,
+                score,
+            }
+        })
+        .collect();
+    (total, variants)
+}
+
+/// Draw ends streak.
+fn streak(players: [&str; 2], outcome: GameOutcome, count: u32) -> Option<Streak> {
+    let username = match outcome {
+        GameOutcome::Win => players[0],
+        GameOutcome::Loss => players[1],
+        GameOutcome::Draw => return None,
+    };
+    Some(Streak {
+        username: String::from(username),
+        count,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(variant: u8, wins: u32, losses: u32, draws: u32) -> VariantRow {
+        VariantRow {
+            _id: variant,
+            wins,
+            losses,
+            draws,
+        }
+    }
+
+    #[test]
+    fn variant_scores_are_totaled() {
+        let rows = vec![row(2, 0, 2, 1), row(4, 1, 0, 0)];
+        let (score, variants) = collect_scores(rows);
+        assert_eq!(score.wins, 1);
+        assert_eq!(score.losses, 2);
+        assert_eq!(score.draws, 1);
+        assert_eq!(variants.len(), 2);
+        assert_eq!(variants[0].score.losses, 2);
+    }
+
+    #[test]
+    fn streak_belongs_to_winner() {
+        let streak = streak(["a", "b"], GameOutcome::Loss, 2);
+        let expected = Streak {
+            username: String::from("b"),
+            count: 2,
+        };
+        assert_eq!(streak, Some(expected));
+    }
+
+    #[test]
+    fn draw_has_no_streak() {
+        assert_eq!(streak(["a", "b"], GameOutcome::Draw, 3), None);
+    }
+}
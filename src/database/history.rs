@@ -0,0 +1,241 @@
+use bson::{doc, DateTime, Document};
+use futures::TryStreamExt;
+use mongodb::Collection;
+use serde::{Deserialize, Serialize};
+use typeshare::typeshare;
+
+use super::{
+    model::{GameStatus, ShuuroGame},
+    stats::{outcome_fields, GameOutcome},
+};
+
+/// Games per page when limit is not set.
+const HISTORY_LIMIT: u64 = 5;
+/// Most games per page.
+const HISTORY_MAX_LIMIT: u64 = 50;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryView {
+    /// Moves are replaced with number of moves.
+    #[default]
+    Summary,
+    Full,
+}
+
+/// Query parameters for game history, every filter is optional.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct HistoryQuery {
+    pub variant: Option<u8>,
+    pub sub_variant: Option<u8>,
+    /// Result from player's side.
+    pub result: Option<GameOutcome>,
+    pub opponent: Option<String>,
+    pub rated: Option<bool>,
+    pub minutes: Option<i64>,
+    pub incr: Option<i64>,
+    /// Start of date range, in milliseconds.
+    pub from: Option<i64>,
+    /// End of date range, in milliseconds.
+    pub to: Option<i64>,
+    /// Returned as `next` from previous page.
+    pub cursor: Option<String>,
+    pub limit: Option<u64>,
+    #[serde(default)]
+    pub view: HistoryView,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct GameHistory {
+    pub games: Vec<ShuuroGame>,
+    /// Cursor for next page, empty on last page.
+    pub next: Option<String>,
+}
+
+/// Position after last game on page, games are sorted by `last_clock` and
+/// `_id`.
+#[derive(Debug, PartialEq, Eq)]
+struct Cursor {
+    last_clock: i64,
+    id: String,
+}
+
+impl Cursor {
+    fn parse(cursor: &str) -> Option<Self> {
+        let (last_clock, id) = cursor.split_once('_')?;
+        Some(Self {
+            last_clock: last_clock.parse().ok()?,
+            id: String::from(id),
+        })
+    }
+
+    fn from_game(game: &ShuuroGame) -> Self {
+        Self {
+            last_clock: game.last_clock.timestamp_millis(),
+            id: game._id.clone(),
+        }
+    }
+
+    fn filter(&self) -> Document {
+        let last_clock = DateTime::from_millis(self.last_clock);
+        doc! {"$or": [
+            {"last_clock": {"$lt": last_clock}},
+            {"last_clock": last_clock, "_id": {"$lt": &self.id}}
+        ]}
+    }
+}
+
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}", self.last_clock, self.id)
+    }
+}
+
+/// Returns `None` only if cursor is invalid.
+pub async fn player_history(
+    db: &Collection<ShuuroGame>,
+    username: &str,
+    query: &HistoryQuery,
+) -> Option<GameHistory> {
+    let limit = query
+        .limit
+        .unwrap_or(HISTORY_LIMIT)
+        .clamp(1, HISTORY_MAX_LIMIT);
+    let pipeline = pipeline(username, query, limit)?;
+    let games: Vec<Document> = match db.aggregate(pipeline).await {
+        Ok(res) => res.try_collect().await.unwrap_or_else(|_| vec![]),
+        Err(_) => vec![],
+    };
+    let mut games: Vec<ShuuroGame> = games
+        .into_iter()
+        .filter_map(|game| bson::from_document(game).ok())
+        .collect();
+    let mut next = None;
+    if games.len() as u64 > limit {
+        games.truncate(limit as usize);
+        next = games.last().map(|game| Cursor::from_game(game).to_string());
+    }
+    Some(GameHistory { games, next })
+}
+
+fn pipeline(
+    username: &str,
+    query: &HistoryQuery,
+    limit: u64,
+) -> Option<Vec<Document>> {
+    let mut pipeline = vec![doc! {"$match": filter(username, query)}];
+    if let Some(outcome) = query.result {
+        pipeline.extend(outcome_fields(username));
+        pipeline.push(doc! {"$match": {
+            "status": {"$ne": GameStatus::Aborted as i32},
+            "outcome": bson::to_bson(&outcome).ok()?
+        }});
+    }
+    if let Some(cursor) = &query.cursor {
+        pipeline.push(doc! {"$match": Cursor::parse(cursor)?.filter()});
+    }
+    pipeline.extend([
+        doc! {"$sort": {"last_clock": -1, "_id": -1}},
+        doc! {"$limit": (limit + 1) as i64},
+    ]);
+    if query.view == HistoryView::Summary {
+        pipeline.push(doc! {"$set": {
+            "history": [[{"$toString": {"$size": {
+                "$ifNull": [{"$arrayElemAt": ["$history", 2]}, []]
+            }}}], [], []],
+            "move_times": [[], []]
+        }});
+    }
+    Some(pipeline)
+}
+
+fn filter(username: &str, query: &HistoryQuery) -> Document {
+    let mut filter = doc! {
+        "players": String::from(username),
+        "status": GameStatus::finished_filter()
+    };
+    if let Some(variant) = query.variant {
+        filter.insert("variant", variant as i32);
+    }
+    if let Some(sub_variant) = query.sub_variant {
+        filter.insert("sub_variant", sub_variant as i32);
+    }
+    if let Some(opponent) = &query.opponent {
+        filter.insert(
+            "players",
+            doc! {"$all": [String::from(username), opponent]},
+        );
+    }
+    match query.rated {
+        Some(true) => {
+            filter.insert("rated", true);
+        }
+        Some(false) => {
+            filter.insert("rated", doc! {"$ne": true});
+        }
+        None => (),
+    }
+    // Stored in milliseconds.
+    if let Some(minutes) = query.minutes {
+        filter.insert("min", minutes * 60_000);
+    }
+    if let Some(incr) = query.incr {
+        filter.insert("incr", incr * 1000);
+    }
+    let mut date = Document::new();
+    if let Some(from) = query.from {
+        date.insert("$gte", DateTime::from_millis(from));
+    }
+    if let Some(to) = query.to {
+        date.insert("$lte", DateTime::from_millis(to));
+    }
+    if !date.is_empty() {
+        filter.insert("last_clock", date);
+    }
+    filter
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_roundtrip() {
+        let cursor = Cursor {
+            last_clock: 1700000000000,
+            id: String::from("abc_def"),
+        };
+        assert_eq!(Cursor::parse(&cursor.to_string()), Some(cursor));
+        assert_eq!(Cursor::parse("abc"), None);
+        assert_eq!(Cursor::parse("x_abc"), None);
+    }
+
+    #[test]
+    fn filters_are_added() {
+        let query = HistoryQuery {
+            variant: Some(2),
+            opponent: Some(String::from("b")),
+            rated: Some(false),
+            minutes: Some(3),
+            from: Some(0),
+            ..Default::default()
+        };
+        let filter = filter("a", &query);
+        assert_eq!(filter.get_i32("variant"), Ok(2));
+        assert_eq!(filter.get_i64("min"), Ok(180_000));
+        assert!(filter.get_document("players").is_ok());
+        assert!(filter.get_document("rated").is_ok());
+        assert!(filter.get_document("last_clock").is_ok());
+        assert!(!filter.contains_key("incr"));
+    }
+
+    #[test]
+    fn invalid_cursor_is_rejected() {
+        let query = HistoryQuery {
+            cursor: Some(String::from("bad")),
+            ..Default::default()
+        };
+        assert!(pipeline("a", &query, 5).is_none());
+    }
+}
@@ -0,0 +1,99 @@
+use bson::{doc, DateTime, Document};
+use mongodb::{error::Error, options::IndexOptions, Collection, IndexModel};
+
+use super::model::{Mongo, SchemaVersion};
+
+/// Bumped when indexes or documents change, migrations compare against it.
+pub const SCHEMA_VERSION: u32 = 1;
+const SCHEMA_ID: &str = "lishuuro";
+
+/// Create missing indexes and record schema version. Existing indexes with
+/// same keys are left as they are.
+pub async fn ensure_indexes(mongo: &Mongo) -> Result<(), Error> {
+    create(
+        &mongo.games,
+        vec![
+            index("players_status_last_clock", doc! {
+                "players": 1, "status": 1, "last_clock": -1
+            }),
+            // Used for loading unfinished games on startup.
+            index("status", doc! {"status": 1}),
+        ],
+    )
+    .await?;
+    create(
+        &mongo.challenges,
+        vec![index("target_status", doc! {"target": 1, "status": 1})],
+    )
+    .await?;
+    create(
+        &mongo.notifications,
+        vec![index("username_read_created_at", doc! {
+            "username": 1, "read": 1, "created_at": -1
+        })],
+    )
+    .await?;
+    create(
+        &mongo.follows,
+        vec![
+            unique("follower_followed", doc! {"follower": 1, "followed": 1}),
+            index("followed", doc! {"followed": 1}),
+        ],
+    )
+    .await?;
+    create(
+        &mongo.sanctions,
+        vec![index("username", doc! {"username": 1})],
+    )
+    .await?;
+    create(
+        &mongo.audit,
+        vec![index("target_created_at", doc! {"target": 1, "created_at": -1})],
+    )
+    .await?;
+    set_schema_version(&mongo.schema, SCHEMA_VERSION).await
+}
+
+/// Version of documents in database, 0 if it was never recorded.
+pub async fn schema_version(db: &Collection<SchemaVersion>) -> u32 {
+    match db.find_one(doc! {"_id": SCHEMA_ID}).await {
+        Ok(Some(schema)) => schema.version,
+        _ => 0,
+    }
+}
+
+/// Version is never lowered, older server can run against newer database.
+pub async fn set_schema_version(
+    db: &Collection<SchemaVersion>,
+    version: u32,
+) -> Result<(), Error> {
+    let update = doc! {
+        "$max": {"version": version},
+        "$set": {"updated_at": DateTime::now()}
+    };
+    db.update_one(doc! {"_id": SCHEMA_ID}, update)
+        .upsert(true)
+        .await?;
+    Ok(())
+}
+
+fn index(name: &str, keys: Document) -> IndexModel {
+    let options = IndexOptions::builder().name(String::from(name)).build();
+    IndexModel::builder().keys(keys).options(options).build()
+}
+
+fn unique(name: &str, keys: Document) -> IndexModel {
+    let options = IndexOptions::builder()
+        .name(String::from(name))
+        .unique(true)
+        .build();
+    IndexModel::builder().keys(keys).options(options).build()
+}
+
+async fn create<T: Send + Sync>(
+    db: &Collection<T>,
+    indexes: Vec<IndexModel>,
+) -> Result<(), Error> {
+    db.create_indexes(indexes).await?;
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use bson::{doc, Document};
+use mongodb::{options::UpdateOptions, Collection};
+use serde::Serialize;
+use typeshare::typeshare;
+
+use super::model::ShuuroGame;
+
+/// Games of anonymous player that can be moved to registered account.
+#[typeshare]
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct MergeOffer {
+    pub from: String,
+    #[typeshare(serialized_as = "u32")]
+    pub games: u64,
+}
+
+/// Games of `from` that don't already have `to` as opponent.
+fn merge_filter(from: &str, to: &str) -> Document {
+    doc! {"players": {"$all": [from], "$nin": [to]}}
+}
+
+/// Returns `None` if anonymous player has no games to merge.
+pub async fn merge_offer(
+    db: &Collection<ShuuroGame>,
+    from: &str,
+    to: &str,
+) -> Option<MergeOffer> {
+    let games = db.count_documents(merge_filter(from, to)).await.ok()?;
+    (games > 0).then(|| MergeOffer {
+        from: String::from(from),
+        games,
+    })
+}
+
+/// Replace `from` with `to` in games, finished and live ones. Games against
+/// `to` are skipped. Returns number of changed games.
+pub async fn merge_games(db: &Collection<ShuuroGame>, from: &str, to: &str) -> u64 {
+    let update = doc! {"$set": {"players.$[player]": to}};
+    let options = UpdateOptions::builder()
+        .array_filters(vec![doc! {"player": from}])
+        .build();
+    match db
+        .update_many(merge_filter(from, to), update)
+        .with_options(options)
+        .await
+    {
+        Ok(res) => res.modified_count,
+        Err(_) => 0,
+    }
+}
+
@@ -0,0 +1,266 @@
+use std::{collections::HashSet, fmt};
+
+use bson::{doc, Bson, DateTime, Document};
+use futures::TryStreamExt;
+use mongodb::{error::Error, options::FindOptions, Collection};
+use serde::Serialize;
+
+use super::{
+    indexes::set_schema_version,
+    model::{AppliedMigration, GameStatus, Mongo},
+};
+
+/// Most document ids listed for each migration in dry run.
+const DRY_RUN_LIMIT: i64 = 100;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    Games,
+    Users,
+}
+
+/// Update of documents that don't match current model.
+///
+/// `filter` must not match documents after `update` is applied, so that
+/// dry run shows only documents that would change.
+pub struct Migration {
+    pub id: &'static str,
+    /// Schema version after this migration.
+    pub version: u32,
+    pub target: Target,
+    pub description: &'static str,
+    filter: fn() -> Document,
+    update: fn() -> Vec<Document>,
+}
+
+/// Migrations in order in which they are applied, never remove or reorder
+/// them.
+pub fn migrations() -> Vec<Migration> {
+    vec![
+        Migration {
+            id: "games_duration_numbers",
+            version: 2,
+            target: Target::Games,
+            description: "store min and incr as milliseconds, not strings",
+            filter: || {
+                doc! {"$or": [
+                    {"min": {"$type": "string"}},
+                    {"incr": {"$type": "string"}}
+                ]}
+            },
+            update: || {
+                vec![doc! {"$set": {
+                    "min": {"$toLong": "$min"},
+                    "incr": {"$toLong": "$incr"}
+                }}]
+            },
+        },
+        Migration {
+            id: "games_sub_variant",
+            version: 3,
+            target: Target::Games,
+            description: "set missing or invalid sub_variant to 100 (none)",
+            // Valid are 0 to 3 and 100.
+            filter: || {
+                doc! {"$or": [
+                    {"sub_variant": {"$not": {"$type": "number"}}},
+                    {"sub_variant": {"$lt": 0}},
+                    {"sub_variant": {"$gt": 3, "$ne": 100}}
+                ]}
+            },
+            update: || vec![doc! {"$set": {"sub_variant": 100}}],
+        },
+        Migration {
+            id: "games_rated",
+            version: 4,
+            target: Target::Games,
+            description: "mark old games as casual",
+            filter: || doc! {"rated": {"$exists": false}},
+            update: || vec![doc! {"$set": {"rated": false}}],
+        },
+        Migration {
+            id: "users_roles_blocked",
+            version: 5,
+            target: Target::Users,
+            description: "add empty roles and blocked lists",
+            filter: || {
+                doc! {"$or": [
+                    {"roles": {"$exists": false}},
+                    {"blocked": {"$exists": false}}
+                ]}
+            },
+            update: || {
+                vec![doc! {"$set": {
+                    "roles": {"$ifNull": ["$roles", []]},
+                    "blocked": {"$ifNull": ["$blocked", []]}
+                }}]
+            },
+        },
+        Migration {
+            id: "games_termination",
+            version: 6,
+            target: Target::Games,
+            description: "add readable termination reason",
+            filter: || doc! {"termination": {"$exists": false}},
+            update: || {
+                let branches: Vec<Document> = GameStatus::ALL
+                    .iter()
+                    .map(|status| {
+                        doc! {
+                            "case": {"$eq": ["$status", *status as i32]},
+                            "then": status.termination()
+                        }
+                    })
+                    .collect();
+                // Same as `GameStatus::from` for unknown numbers.
+                let default = doc! {"$cond": [
+                    {"$gt": ["$status", 0]},
+                    GameStatus::Aborted.termination(),
+                    GameStatus::NotStarted.termination()
+                ]};
+                vec![doc! {"$set": {
+                    "termination": {"$switch": {
+                        "branches": branches,
+                        "default": default
+                    }}
+                }}]
+            },
+        },
+    ]
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MigrationReport {
+    pub id: &'static str,
+    pub version: u32,
+    pub target: Target,
+    pub description: &'static str,
+    /// Documents that match migration filter.
+    pub matched: u64,
+    pub modified: u64,
+    /// Ids of documents that would change, only in dry run.
+    pub documents: Vec<String>,
+    pub applied: bool,
+}
+
+/// Migration that failed, later migrations are not applied because they can
+/// depend on it.
+#[derive(Debug)]
+pub struct MigrationError {
+    pub id: &'static str,
+    pub error: Error,
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "migration {} failed: {}", self.id, self.error)
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+/// Apply pending migrations in order. In dry run nothing is changed, report
+/// contains documents that would change.
+pub async fn run_migrations(
+    mongo: &Mongo,
+    dry_run: bool,
+) -> Result<Vec<MigrationReport>, MigrationError> {
+    let applied = applied_migrations(&mongo.migrations).await;
+    let mut reports = vec![];
+    for migration in migrations() {
+        if applied.contains(migration.id) {
+            continue;
+        }
+        let failed = |error| MigrationError {
+            id: migration.id,
+            error,
+        };
+        let db = collection(mongo, migration.target);
+        let filter = (migration.filter)();
+        let matched = db.count_documents(filter.clone()).await.map_err(failed)?;
+        let mut report = MigrationReport {
+            id: migration.id,
+            version: migration.version,
+            target: migration.target,
+            description: migration.description,
+            matched,
+            modified: 0,
+            documents: vec![],
+            applied: false,
+        };
+        if dry_run {
+            report.documents = document_ids(&db, filter).await;
+            reports.push(report);
+            continue;
+        }
+        let res = db
+            .update_many(filter, (migration.update)())
+            .await
+            .map_err(failed)?;
+        report.modified = res.modified_count;
+        let record = AppliedMigration {
+            _id: String::from(migration.id),
+            version: migration.version,
+            modified: report.modified,
+            applied_at: DateTime::now(),
+        };
+        mongo.migrations.insert_one(record).await.map_err(failed)?;
+        set_schema_version(&mongo.schema, migration.version)
+            .await
+            .map_err(failed)?;
+        report.applied = true;
+        reports.push(report);
+    }
+    Ok(reports)
+}
+
+pub async fn applied_migrations(
+    db: &Collection<AppliedMigration>,
+) -> HashSet<String> {
+    let Ok(res) = db.find(doc! {}).await else {
+        return HashSet::new();
+    };
+    let applied: Vec<AppliedMigration> =
+        res.try_collect().await.unwrap_or_else(|_| vec![]);
+    applied.into_iter().map(|migration| migration._id).collect()
+}
+
+fn collection(mongo: &Mongo, target: Target) -> Collection<Document> {
+    match target {
+        Target::Games => mongo.games.clone_with_type::<Document>(),
+        Target::Users => mongo.players.clone_with_type::<Document>(),
+    }
+}
+
+async fn document_ids(db: &Collection<Document>, filter: Document) -> Vec<String> {
+    let options = FindOptions::builder()
+        .projection(doc! {"_id": 1})
+        .limit(Some(DRY_RUN_LIMIT))
+        .build();
+    let Ok(res) = db.find(filter).with_options(options).await else {
+        return vec![];
+    };
+    let docs: Vec<Document> = res.try_collect().await.unwrap_or_else(|_| vec![]);
+    docs.into_iter()
+        .filter_map(|doc| match doc.get("_id")? {
+            Bson::String(id) => Some(id.clone()),
+            id => Some(id.to_string()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::indexes::SCHEMA_VERSION;
+
+    #[test]
+    fn migrations_are_ordered() {
+        let migrations = migrations();
+        let ids: HashSet<&str> = migrations.iter().map(|m| m.id).collect();
+        assert_eq!(ids.len(), migrations.len());
+        assert!(migrations.windows(2).all(|m| m[0].version < m[1].version));
+        assert!(migrations[0].version > SCHEMA_VERSION);
+    }
+}
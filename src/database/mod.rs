@@ -1,38 +1,96 @@
-use std::{env, sync::Arc};
+use std::{fmt, sync::Arc};
 
+use clock::time_source::{system_clock, Clock};
+use indexes::ensure_indexes;
+use migrations::{run_migrations, MigrationError, MigrationReport};
 use model::Mongo;
+use model::Role;
 use redis::RedisCli;
+use roles::change_role;
 
-use crate::{lichess::MyKey, websockets::channels::ai::Pockets};
+use crate::{config::Config, lichess::MyKey, websockets::channels::ai::Pockets};
 
+pub mod cleanup;
 pub mod clock;
+pub mod crosstable;
+pub mod history;
+pub mod indexes;
+pub mod merge;
+pub mod migrations;
 pub mod model;
+pub mod moderation;
+pub mod notifications;
 pub mod redis;
+pub mod replay;
+pub mod roles;
 pub mod serde_helpers;
+pub mod stats;
+pub mod verify;
 
 #[derive(Clone)]
 pub struct Database {
     pub redis: RedisCli,
     pub mongo: Mongo,
     pub key: MyKey,
-    pub mod1: String,
     pub pockets: Arc<Pockets>,
+    pub clock: Clock,
 }
 
 impl Database {
-    /// Create databases.
-    pub async fn new() -> Self {
-        let redis = RedisCli::default().await;
-        let mongo = Mongo::new().await;
-        let key = MyKey::default();
-        let mod1 = env::var("LOGIN_STATE").unwrap();
-        let pockets = Arc::new(Pockets::new());
+    /// Connect to databases without changing them.
+    pub async fn connect(config: &Config) -> Self {
         Self {
-            redis,
-            mongo,
-            key,
-            mod1,
-            pockets,
+            redis: RedisCli::new(&config.redis).await,
+            mongo: Mongo::new(&config.mongo, &config.mongo_db).await,
+            key: MyKey::from(config),
+            pockets: Arc::new(Pockets::new(&config.pockets)),
+            clock: system_clock(),
         }
     }
+
+    /// Create databases with indexes, run migrations and grant admins.
+    pub async fn new(config: &Config) -> Result<(Self, SetupReport), SetupError> {
+        let db = Self::connect(config).await;
+        let mongo = &db.mongo;
+        ensure_indexes(mongo).await.map_err(SetupError::Indexes)?;
+        let mut report = SetupReport::default();
+        if config.migrate {
+            report.migrations = run_migrations(mongo, false)
+                .await
+                .map_err(SetupError::Migration)?;
+        }
+        for admin in &config.admins {
+            let granted =
+                change_role(mongo, "config", admin, Role::Admin, true, None).await;
+            if granted.is_none() {
+                report.unknown_admins.push(String::from(admin));
+            }
+        }
+        Ok((db, report))
+    }
 }
+
+/// Changes made by `Database::new`.
+#[derive(Debug, Default)]
+pub struct SetupReport {
+    pub migrations: Vec<MigrationReport>,
+    /// Admins from config that are not registered players.
+    pub unknown_admins: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum SetupError {
+    Indexes(mongodb::error::Error),
+    Migration(MigrationError),
+}
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Indexes(e) => write!(f, "failed to create indexes: {}", e),
+            Self::Migration(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SetupError {}
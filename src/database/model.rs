@@ -1,36 +1,79 @@
 use crate::websockets::channels::game_requests::GameRequest;
 use typeshare::typeshare;
 
-use super::{clock::time_control::TimeControl, serde_helpers::*};
-use bson::DateTime;
+use super::{
+    clock::time_control::{MoveTime, TimeControl},
+    serde_helpers::*,
+};
+use bson::{doc, oid::ObjectId, DateTime, Document};
 use chrono::Duration;
 use mongodb::{options::ClientOptions, Client, Collection};
 use serde::{Deserialize, Serialize};
-use shuuro::{SubVariant, Variant};
-use std::env;
+use serde_repr::Serialize_repr;
+use shuuro::{Color, SubVariant, Variant};
 
 #[derive(Clone)]
 pub struct Mongo {
     pub players: Collection<Player>,
     pub games: Collection<ShuuroGame>,
+    pub audit: Collection<AuditEntry>,
+    pub sanctions: Collection<Sanction>,
+    pub challenges: Collection<Challenge>,
+    pub notifications: Collection<Notification>,
+    pub follows: Collection<Follow>,
+    pub schema: Collection<SchemaVersion>,
+    pub migrations: Collection<AppliedMigration>,
 }
 
 impl Mongo {
     /// Create mongodb connection for all collections.
-    pub async fn new() -> Self {
-        let addr = env::var("MONGO").expect("mongo addr not found");
+    pub async fn new(addr: &str, name: &str) -> Self {
         let mut client_options = ClientOptions::parse(addr)
             .await
             .expect("No client available");
         client_options.app_name = Some("lishuuro".to_string());
         let client = Client::with_options(client_options).expect("client not found");
-        let db = client.database("lishuuro");
+        let db = client.database(name);
         let players = db.collection::<Player>("users");
         let games = db.collection::<ShuuroGame>("shuuroGames");
-        Mongo { players, games }
+        let audit = db.collection::<AuditEntry>("auditLog");
+        let sanctions = db.collection::<Sanction>("sanctions");
+        let challenges = db.collection::<Challenge>("challenges");
+        let notifications = db.collection::<Notification>("notifications");
+        let follows = db.collection::<Follow>("follows");
+        let schema = db.collection::<SchemaVersion>("schema");
+        let migrations = db.collection::<AppliedMigration>("migrations");
+        Mongo {
+            players,
+            games,
+            audit,
+            sanctions,
+            challenges,
+            notifications,
+            follows,
+            schema,
+            migrations,
+        }
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Single document with version of database schema.
+pub struct SchemaVersion {
+    pub _id: String,
+    pub version: u32,
+    pub updated_at: DateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Migration that was already applied, `_id` is migration id.
+pub struct AppliedMigration {
+    pub _id: String,
+    pub version: u32,
+    pub modified: u64,
+    pub applied_at: DateTime,
+}
+
 pub type History = (Vec<String>, Vec<String>, Vec<String>);
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -41,6 +84,228 @@ pub struct Player {
     pub reg: bool,
     #[typeshare(serialized_as = "Value")]
     pub created_at: DateTime,
+    #[serde(default)]
+    pub roles: Vec<Role>,
+    /// Players that can't challenge this player or write to them.
+    #[serde(default)]
+    pub blocked: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[typeshare]
+pub enum Role {
+    Admin,
+    Moderator,
+}
+
+impl Role {
+    /// Admin can do everything that moderator can.
+    pub fn allows(&self, required: Role) -> bool {
+        *self == Role::Admin || *self == required
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "content")]
+#[typeshare]
+pub enum AuditAction {
+    GrantRole(Role),
+    RevokeRole(Role),
+    Sanction(SanctionKind),
+    LiftSanction(SanctionKind),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[typeshare]
+pub enum SanctionKind {
+    /// Player can't create game requests.
+    Ban,
+    /// Player can't write in chat.
+    Mute,
+    /// Player doesn't know it, but chat messages are visible only to them.
+    ShadowBan,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[typeshare]
+pub struct Sanction {
+    #[typeshare(serialized_as = "String")]
+    pub _id: ObjectId,
+    pub username: String,
+    pub kind: SanctionKind,
+    pub reason: String,
+    pub moderator: String,
+    #[typeshare(serialized_as = "Value")]
+    pub created_at: DateTime,
+    /// Permanent if empty.
+    #[typeshare(serialized_as = "Option<Value>")]
+    pub expires_at: Option<DateTime>,
+}
+
+impl Sanction {
+    pub fn new(
+        username: &str,
+        kind: SanctionKind,
+        reason: &str,
+        moderator: &str,
+        minutes: Option<u32>,
+        created_at: DateTime,
+    ) -> Self {
+        let expires_at = minutes.map(|minutes| {
+            let minutes = i64::from(minutes);
+            DateTime::from_millis(created_at.timestamp_millis() + minutes * 60_000)
+        });
+        Self {
+            _id: ObjectId::new(),
+            username: String::from(username),
+            kind,
+            reason: String::from(reason),
+            moderator: String::from(moderator),
+            created_at,
+            expires_at,
+        }
+    }
+
+    /// Permanent or not expired yet.
+    pub fn is_active(&self, now: DateTime) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[typeshare]
+/// Record of one moderation or admin action.
+pub struct AuditEntry {
+    #[typeshare(serialized_as = "String")]
+    pub _id: ObjectId,
+    pub actor: String,
+    pub target: String,
+    pub action: AuditAction,
+    pub reason: Option<String>,
+    #[typeshare(serialized_as = "Value")]
+    pub created_at: DateTime,
+}
+
+impl AuditEntry {
+    pub fn new(
+        actor: &str,
+        target: &str,
+        action: AuditAction,
+        reason: Option<String>,
+    ) -> Self {
+        Self {
+            _id: ObjectId::new(),
+            actor: String::from(actor),
+            target: String::from(target),
+            action,
+            reason,
+            created_at: DateTime::now(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[typeshare]
+pub enum ChallengeStatus {
+    Pending,
+    Accepted,
+    Declined,
+    Expired,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[typeshare]
+/// Game request sent to one player, game is created only when it's accepted.
+pub struct Challenge {
+    pub _id: String,
+    pub challenger: String,
+    pub target: String,
+    pub request: GameRequest,
+    pub status: ChallengeStatus,
+    /// Reason given by target when declining.
+    pub reason: Option<String>,
+    /// Game id, set when challenge is accepted.
+    pub game: Option<String>,
+    #[typeshare(serialized_as = "Value")]
+    pub created_at: DateTime,
+    #[typeshare(serialized_as = "Value")]
+    pub expires_at: DateTime,
+}
+
+impl Challenge {
+    pub fn new(
+        id: &str,
+        challenger: &str,
+        target: &str,
+        request: GameRequest,
+        created_at: DateTime,
+        timeout: Duration,
+    ) -> Self {
+        let expires_at = DateTime::from_millis(
+            created_at.timestamp_millis() + timeout.num_milliseconds(),
+        );
+        Self {
+            _id: String::from(id),
+            challenger: String::from(challenger),
+            target: String::from(target),
+            request,
+            status: ChallengeStatus::Pending,
+            reason: None,
+            game: None,
+            created_at,
+            expires_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "content")]
+#[typeshare]
+pub enum NotificationKind {
+    Challenge { id: String, challenger: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[typeshare]
+/// Stored so that offline players see it when they come back.
+pub struct Notification {
+    #[typeshare(serialized_as = "String")]
+    pub _id: ObjectId,
+    pub username: String,
+    pub kind: NotificationKind,
+    pub read: bool,
+    #[typeshare(serialized_as = "Value")]
+    pub created_at: DateTime,
+}
+
+impl Notification {
+    pub fn new(
+        username: &str,
+        kind: NotificationKind,
+        created_at: DateTime,
+    ) -> Self {
+        Self {
+            _id: ObjectId::new(),
+            username: String::from(username),
+            kind,
+            read: false,
+            created_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[typeshare]
+pub struct Follow {
+    #[typeshare(serialized_as = "String")]
+    pub _id: ObjectId,
+    pub follower: String,
+    pub followed: String,
+    #[typeshare(serialized_as = "Value")]
+    pub created_at: DateTime,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -64,8 +329,11 @@ pub struct ShuuroGame {
     #[typeshare(serialized_as = "String")]
     pub last_clock: DateTime,
     pub current_stage: u8,
-    pub result: u8,
-    pub status: i32,
+    pub result: GameResult,
+    pub status: GameStatus,
+    /// Readable reason of game end, set together with status.
+    #[serde(default)]
+    pub termination: String,
     #[serde(serialize_with = "serialize_variant")]
     #[serde(deserialize_with = "deserialize_variant")]
     #[typeshare(serialized_as = "u8")]
@@ -75,6 +343,11 @@ pub struct ShuuroGame {
     pub sfen: String,
     #[typeshare(serialized_as = "[Vec<String>; 3]")]
     pub history: History,
+    /// Clock after each placement and fight move, in same order as
+    /// `history.1` and `history.2`. Empty for old games.
+    #[serde(default)]
+    #[typeshare(serialized_as = "[Vec<MoveTime>; 2]")]
+    pub move_times: (Vec<MoveTime>, Vec<MoveTime>),
     pub game_start: String,
     pub placement_start: String,
     pub tc: TimeControl,
@@ -84,6 +357,9 @@ pub struct ShuuroGame {
     #[serde(deserialize_with = "deserialize_subvariant")]
     #[typeshare(serialized_as = "Option<u8>")]
     pub sub_variant: Option<SubVariant>,
+    /// Every game is casual for now, old games don't have this field.
+    #[serde(default)]
+    pub rated: bool,
 }
 
 impl From<(&GameRequest, &[String; 2], &str)> for ShuuroGame {
@@ -98,18 +374,308 @@ impl From<(&GameRequest, &[String; 2], &str)> for ShuuroGame {
             clocks: [clock, clock],
             last_clock: DateTime::now(),
             current_stage: 0,
-            result: 2,
-            status: -2,
+            result: GameResult::Draw,
+            status: GameStatus::NotStarted,
+            termination: String::from(GameStatus::NotStarted.termination()),
             variant: f.0.variant,
             credits: [800, 800],
             hands: [String::from(""), String::from("")],
             sfen: String::from(""),
             history: (vec![], vec![], vec![]),
+            move_times: (vec![], vec![]),
             game_start: String::default(),
             placement_start: String::default(),
             tc: TimeControl::new(f.0.minutes, f.0.incr),
             draws: [false, false],
             sub_variant: f.0.sub_variant,
+            rated: false,
+        }
+    }
+}
+
+impl ShuuroGame {
+    /// Status, result and termination must always be changed together.
+    pub fn set_status(&mut self, status: GameStatus, result: GameResult) {
+        self.status = status;
+        self.result = result;
+        self.termination = String::from(status.termination());
+    }
+
+    /// Winner of finished game.
+    pub fn winner(&self) -> Option<Color> {
+        let color = self.result.color()?;
+        match self.status {
+            GameStatus::Checkmate => Some(color),
+            GameStatus::Resign
+            | GameStatus::Timeout
+            | GameStatus::FirstMoveError => Some(color.flip()),
+            _ => None,
+        }
+    }
+}
+
+/// Stored and sent as number, existing values must never change.
+///
+/// Unknown numbers from old documents are read as `Aborted` if positive,
+/// otherwise as `NotStarted`.
+#[derive(Serialize_repr, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(from = "i64")]
+#[repr(i32)]
+#[typeshare]
+pub enum GameStatus {
+    NotStarted = -2,
+    Ongoing = -1,
+    Checkmate = 1,
+    Stalemate = 3,
+    Repetition = 4,
+    Agreement = 5,
+    Material = 6,
+    Resign = 7,
+    Timeout = 8,
+    /// First move of fight stage gives check.
+    FirstMoveError = 9,
+    Aborted = 10,
+}
+
+impl GameStatus {
+    pub const ALL: [GameStatus; 11] = [
+        GameStatus::NotStarted,
+        GameStatus::Ongoing,
+        GameStatus::Checkmate,
+        GameStatus::Stalemate,
+        GameStatus::Repetition,
+        GameStatus::Agreement,
+        GameStatus::Material,
+        GameStatus::Resign,
+        GameStatus::Timeout,
+        GameStatus::FirstMoveError,
+        GameStatus::Aborted,
+    ];
+
+    pub fn is_finished(&self) -> bool {
+        *self as i32 > 0
+    }
+
+    /// Finished with draw, whatever is stored in `result`.
+    pub fn is_draw(&self) -> bool {
+        matches!(
+            self,
+            GameStatus::Stalemate
+                | GameStatus::Repetition
+                | GameStatus::Agreement
+                | GameStatus::Material
+        )
+    }
+
+    /// Stored numbers of statuses for which `keep` returns true.
+    pub fn codes(keep: impl Fn(&GameStatus) -> bool) -> Vec<i32> {
+        Self::ALL
+            .iter()
+            .filter(|status| keep(status))
+            .map(|status| *status as i32)
+            .collect()
+    }
+
+    /// Condition on stored status of finished games, aborted ones included.
+    /// Range also matches old codes that `From<i64>` still reads.
+    pub fn finished_filter() -> Document {
+        doc! {"$gt": 0}
+    }
+
+    /// Finished games with result on board, aborted ones are left out.
+    pub fn decided_codes() -> Vec<i32> {
+        Self::codes(|status| status.is_finished() && *status != GameStatus::Aborted)
+    }
+
+    pub fn unfinished_filter() -> Document {
+        doc! {"$lt": 0}
+    }
+
+    pub fn draw_codes() -> Vec<i32> {
+        Self::codes(GameStatus::is_draw)
+    }
+
+    /// Status stored as `code`, unlike `From<i64>` unknown numbers are `None`.
+    pub fn from_code(code: i64) -> Option<GameStatus> {
+        Self::ALL.into_iter().find(|status| *status as i64 == code)
+    }
+
+    /// Status and result for game ended by admin. Result is required for
+    /// statuses with winner, other statuses are always draw.
+    pub fn admin_end(
+        status: i64,
+        result: Option<i64>,
+    ) -> Result<(GameStatus, GameResult), String> {
+        let status = GameStatus::from_code(status)
+            .filter(GameStatus::is_finished)
+            .ok_or_else(|| format!("{} is not status of finished game", status))?;
+        let result = match result {
+            Some(result) => GameResult::from_code(result)
+                .ok_or_else(|| format!("{} is not result", result))?,
+            None => GameResult::Draw,
+        };
+        let decisive = !status.is_draw() && status != GameStatus::Aborted;
+        match (decisive, result) {
+            (true, GameResult::Draw) => {
+                Err(format!("{} needs result 0 or 1", status.termination()))
+            }
+            (false, GameResult::White | GameResult::Black) => {
+                Err(format!("{} is always draw", status.termination()))
+            }
+            _ => Ok((status, result)),
         }
     }
+
+    pub fn termination(&self) -> &'static str {
+        match self {
+            GameStatus::NotStarted => "not started",
+            GameStatus::Ongoing => "ongoing",
+            GameStatus::Checkmate => "checkmate",
+            GameStatus::Stalemate => "stalemate",
+            GameStatus::Repetition => "draw by repetition",
+            GameStatus::Agreement => "draw by agreement",
+            GameStatus::Material => "insufficient material",
+            GameStatus::Resign => "resignation",
+            GameStatus::Timeout => "time out",
+            GameStatus::FirstMoveError => "first move check",
+            GameStatus::Aborted => "aborted",
+        }
+    }
+}
+
+impl From<i64> for GameStatus {
+    fn from(value: i64) -> Self {
+        match value {
+            -1 => GameStatus::Ongoing,
+            1 => GameStatus::Checkmate,
+            3 => GameStatus::Stalemate,
+            4 => GameStatus::Repetition,
+            5 => GameStatus::Agreement,
+            6 => GameStatus::Material,
+            7 => GameStatus::Resign,
+            8 => GameStatus::Timeout,
+            9 => GameStatus::FirstMoveError,
+            value if value > 0 => GameStatus::Aborted,
+            _ => GameStatus::NotStarted,
+        }
+    }
+}
+
+/// Color in `result` field: winner after checkmate, loser after resign,
+/// timeout and first move error. `Draw` is also used while game is not
+/// finished and when both players lost on time.
+#[derive(Serialize_repr, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(from = "i64")]
+#[repr(u8)]
+#[typeshare]
+pub enum GameResult {
+    White = 0,
+    Black = 1,
+    Draw = 2,
+}
+
+impl GameResult {
+    /// Result stored as `code`, unlike `From<i64>` unknown numbers are `None`.
+    pub fn from_code(code: i64) -> Option<GameResult> {
+        match code {
+            0 => Some(GameResult::White),
+            1 => Some(GameResult::Black),
+            2 => Some(GameResult::Draw),
+            _ => None,
+        }
+    }
+
+    pub fn color(&self) -> Option<Color> {
+        match self {
+            GameResult::White => Some(Color::White),
+            GameResult::Black => Some(Color::Black),
+            GameResult::Draw => None,
+        }
+    }
+}
+
+impl From<Color> for GameResult {
+    fn from(color: Color) -> Self {
+        match color {
+            Color::White => GameResult::White,
+            Color::Black => GameResult::Black,
+            _ => GameResult::Draw,
+        }
+    }
+}
+
+impl From<i64> for GameResult {
+    fn from(value: i64) -> Self {
+        match value {
+            0 => GameResult::White,
+            1 => GameResult::Black,
+            _ => GameResult::Draw,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_status_numbers_are_converted() {
+        let status: GameStatus = bson::from_bson(bson::Bson::Int32(7)).unwrap();
+        assert_eq!(status, GameStatus::Resign);
+        let status: GameStatus = bson::from_bson(bson::Bson::Int64(2)).unwrap();
+        assert_eq!(status, GameStatus::Aborted);
+        let result: GameResult = bson::from_bson(bson::Bson::Int32(5)).unwrap();
+        assert_eq!(result, GameResult::Draw);
+        assert_eq!(bson::to_bson(&GameStatus::NotStarted).unwrap(), (-2).into());
+        assert_eq!(serde_json::json!(GameResult::Black), serde_json::json!(1));
+    }
+
+    #[test]
+    fn every_status_roundtrips() {
+        for status in GameStatus::ALL {
+            assert_eq!(GameStatus::from(status as i64), status);
+        }
+    }
+
+    #[test]
+    fn status_codes_match_stored_numbers() {
+        assert_eq!(GameStatus::decided_codes(), [1, 3, 4, 5, 6, 7, 8, 9]);
+        // Ranges of filters split statuses same as `is_finished`.
+        let finished = GameStatus::codes(GameStatus::is_finished);
+        assert!(finished.iter().all(|code| *code > 0));
+        let unfinished = GameStatus::codes(|status| !status.is_finished());
+        assert_eq!(unfinished, [-2, -1]);
+        assert_eq!(GameStatus::draw_codes(), [3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn admin_end_checks_status_and_result() {
+        let resign = GameStatus::admin_end(7, Some(1));
+        assert_eq!(resign, Ok((GameStatus::Resign, GameResult::Black)));
+        let stalemate = GameStatus::admin_end(3, None);
+        assert_eq!(stalemate, Ok((GameStatus::Stalemate, GameResult::Draw)));
+        // Unknown numbers are not converted to aborted.
+        assert!(GameStatus::admin_end(2, None).is_err());
+        assert!(GameStatus::admin_end(-1, None).is_err());
+        assert!(GameStatus::admin_end(8, None).is_err());
+        assert!(GameStatus::admin_end(8, Some(5)).is_err());
+        assert!(GameStatus::admin_end(5, Some(0)).is_err());
+    }
+
+    #[test]
+    fn sanction_expires_after_minutes() {
+        let kind = SanctionKind::Mute;
+        let now = DateTime::from_millis(1_700_000_000_000);
+        let later = |minutes: i64| {
+            DateTime::from_millis(now.timestamp_millis() + minutes * 60_000)
+        };
+        let sanction = Sanction::new("a", kind, "spam", "mod", Some(90), now);
+        assert_eq!(sanction.created_at, now);
+        assert_eq!(sanction.expires_at, Some(later(90)));
+        assert!(sanction.is_active(later(89)));
+        assert!(!sanction.is_active(later(90)));
+        let sanction = Sanction::new("a", kind, "spam", "mod", None, now);
+        assert_eq!(sanction.expires_at, None);
+        assert!(sanction.is_active(later(100_000)));
+    }
 }
@@ -0,0 +1,54 @@
+use bson::DateTime;
+
+use super::{
+    clock::queries::{add_audit_entry, add_sanction, get_player, lift_sanction},
+    model::{AuditAction, AuditEntry, Mongo, Sanction, SanctionKind},
+};
+
+/// Add sanction and write it to audit log.
+///
+/// Returns `None` if player is not found.
+pub async fn sanction_player(
+    mongo: &Mongo,
+    moderator: &str,
+    username: &str,
+    kind: SanctionKind,
+    reason: &str,
+    minutes: Option<u32>,
+    now: DateTime,
+) -> Option<Sanction> {
+    get_player(&mongo.players, username).await?;
+    let sanction = Sanction::new(username, kind, reason, moderator, minutes, now);
+    add_sanction(&mongo.sanctions, &sanction).await;
+    let entry = AuditEntry::new(
+        moderator,
+        username,
+        AuditAction::Sanction(kind),
+        Some(String::from(reason)),
+    );
+    add_audit_entry(&mongo.audit, &entry).await;
+    Some(sanction)
+}
+
+/// Lift active sanctions of given kind, returns `false` if there were none.
+pub async fn lift_player_sanction(
+    mongo: &Mongo,
+    moderator: &str,
+    username: &str,
+    kind: SanctionKind,
+    reason: &str,
+    now: DateTime,
+) -> bool {
+    let lifted = lift_sanction(&mongo.sanctions, username, kind, now).await;
+    if lifted == 0 {
+        return false;
+    }
+    let entry = AuditEntry::new(
+        moderator,
+        username,
+        AuditAction::LiftSanction(kind),
+        Some(String::from(reason)),
+    );
+    add_audit_entry(&mongo.audit, &entry).await;
+    true
+}
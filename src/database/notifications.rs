@@ -0,0 +1,35 @@
+use tokio::sync::mpsc::Sender;
+
+use crate::websockets::channels::players::PlayersMessage;
+
+use super::{
+    clock::queries::{add_notification, unread_notifications},
+    model::{Notification, NotificationKind},
+    Database,
+};
+
+/// Store notification and push new unread count to player's sockets.
+pub async fn notify_player(
+    db: &Database,
+    players: &Sender<PlayersMessage>,
+    username: &str,
+    kind: NotificationKind,
+) {
+    let notification = Notification::new(username, kind, db.clock.bson_now());
+    add_notification(&db.mongo.notifications, &notification).await;
+    push_unread_count(db, players, username).await;
+}
+
+pub async fn push_unread_count(
+    db: &Database,
+    players: &Sender<PlayersMessage>,
+    username: &str,
+) {
+    let count = unread_notifications(&db.mongo.notifications, username).await;
+    let _ = players
+        .send(PlayersMessage::UnreadNotifications {
+            player: String::from(username),
+            count,
+        })
+        .await;
+}
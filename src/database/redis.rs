@@ -9,15 +9,28 @@ use axum_extra::{headers::Cookie, typed_header::TypedHeader};
 use bson::DateTime;
 use hyper::{header::SET_COOKIE, HeaderMap, StatusCode};
 use mongodb::Collection;
-use redis::{aio::ConnectionManager, AsyncCommands, Client};
-use serde::{Deserialize, Serialize};
-use std::env;
+use redis::{
+    aio::ConnectionManager, AsyncCommands, Client, ExistenceCheck, SetExpiry,
+    SetOptions,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
-use crate::{lichess::cookies, AppState};
+use crate::{
+    lichess::{cookies, login_helpers::create_challenge},
+    AppState,
+};
 
 use super::{clock::queries::create_player, model::Player};
 
 pub const AXUM_SESSION_COOKIE_NAME: &str = "axum_session";
+/// Last seen time of session is saved at most this often, in milliseconds.
+const LAST_SEEN_INTERVAL: i64 = 5 * 60 * 1000;
 
 /// Struct representing current user.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,6 +40,16 @@ pub struct UserSession {
     pub code_verifier: String,
     pub session: String,
     pub is_new: bool,
+    /// Anonymous name used before login, while its games can be merged.
+    #[serde(default)]
+    pub merge_from: Option<String>,
+    /// In milliseconds, zero for sessions created before it was saved.
+    #[serde(default)]
+    pub created_at: i64,
+    #[serde(default)]
+    pub last_seen: i64,
+    #[serde(default)]
+    pub user_agent: String,
     #[serde(skip_serializing)]
     #[serde(skip_deserializing)]
     pub cookie_value: CookieValue,
@@ -39,13 +62,19 @@ impl UserSession {
         reg: bool,
         code_verifier: &str,
         cookie_value: CookieValue,
+        now: DateTime,
     ) -> Self {
+        let now = now.timestamp_millis();
         Self {
             username: String::from(username),
             reg,
             code_verifier: String::from(code_verifier),
             session: String::from(session),
             is_new: true,
+            merge_from: None,
+            created_at: now,
+            last_seen: now,
+            user_agent: String::new(),
             cookie_value,
         }
     }
@@ -54,6 +83,11 @@ impl UserSession {
         self.is_new = false;
     }
 
+    /// Identifies session in sessions list, session key is never shown.
+    pub fn public_id(&self) -> String {
+        create_challenge(&self.session)
+    }
+
     pub fn headers(&self) -> HeaderMap {
         let mut headers = HeaderMap::new();
         if self.is_new {
@@ -104,28 +138,201 @@ impl Default for CookieValue {
     }
 }
 
-/// Redis connection. Used only for saving session.
+/// Backend that holds session values.
+#[derive(Clone)]
+enum SessionStore {
+    Redis(ConnectionManager),
+    /// In-process store, used by tests.
+    Memory(Arc<Mutex<HashMap<String, (String, Instant)>>>),
+}
+
+/// Redis connection. Used for sessions and cached values.
 #[derive(Clone)]
 pub struct RedisCli {
-    con: ConnectionManager,
+    con: SessionStore,
 }
 
 impl RedisCli {
-    pub async fn default() -> Self {
-        let addr = env::var("REDIS").expect("redis addr not found");
+    pub async fn new(addr: &str) -> Self {
         let cli = Client::open(addr).unwrap();
         let con = ConnectionManager::new(cli).await.unwrap();
-        Self { con }
+        Self {
+            con: SessionStore::Redis(con),
+        }
+    }
+
+    /// Values are kept in memory.
+    pub fn memory() -> Self {
+        Self {
+            con: SessionStore::Memory(Arc::new(Mutex::new(HashMap::new()))),
+        }
+    }
+
+    async fn get_value(&mut self, key: &str) -> Option<String> {
+        match &mut self.con {
+            SessionStore::Redis(con) => {
+                con.get::<String, String>(String::from(key)).await.ok()
+            }
+            SessionStore::Memory(map) => map
+                .lock()
+                .unwrap()
+                .get(key)
+                .filter(|(_, expires_at)| *expires_at > Instant::now())
+                .map(|(value, _)| value.clone()),
+        }
+    }
+
+    async fn set_value(&mut self, key: &str, value: String, ttl: usize) {
+        match &mut self.con {
+            SessionStore::Redis(con) => {
+                let _ = con
+                    .set::<String, String, String>(String::from(key), value)
+                    .await;
+                let _e = con
+                    .expire::<String, usize>(String::from(key), ttl as i64)
+                    .await;
+            }
+            SessionStore::Memory(map) => {
+                let expires_at = Instant::now() + Duration::from_secs(ttl as u64);
+                map.lock()
+                    .unwrap()
+                    .insert(String::from(key), (value, expires_at));
+            }
+        }
+    }
+
+    /// Replace existing value, its expiry is kept.
+    async fn replace_value(&mut self, key: &str, value: String) {
+        match &mut self.con {
+            SessionStore::Redis(con) => {
+                let options = SetOptions::default()
+                    .conditional_set(ExistenceCheck::XX)
+                    .with_expiration(SetExpiry::KEEPTTL);
+                let _ = con
+                    .set_options::<String, String, ()>(
+                        String::from(key),
+                        value,
+                        options,
+                    )
+                    .await;
+            }
+            SessionStore::Memory(map) => {
+                if let Some((old, _)) = map.lock().unwrap().get_mut(key) {
+                    *old = value;
+                }
+            }
+        }
+    }
+
+    /// Add member to set, set expires after `ttl` seconds.
+    async fn add_member(&mut self, key: &str, member: &str, ttl: usize) {
+        match &mut self.con {
+            SessionStore::Redis(con) => {
+                let _ = con
+                    .sadd::<String, String, usize>(
+                        String::from(key),
+                        String::from(member),
+                    )
+                    .await;
+                let _e = con
+                    .expire::<String, usize>(String::from(key), ttl as i64)
+                    .await;
+            }
+            SessionStore::Memory(map) => {
+                let mut map = map.lock().unwrap();
+                let mut members = memory_members(&map, key);
+                if !members.iter().any(|item| item == member) {
+                    members.push(String::from(member));
+                }
+                let expires_at = Instant::now() + Duration::from_secs(ttl as u64);
+                let members = serde_json::to_string(&members).unwrap();
+                map.insert(String::from(key), (members, expires_at));
+            }
+        }
+    }
+
+    /// Returns `false` if member was not in set.
+    async fn remove_member(&mut self, key: &str, member: &str) -> bool {
+        match &mut self.con {
+            SessionStore::Redis(con) => {
+                let key = String::from(key);
+                let member = String::from(member);
+                con.srem::<String, String, usize>(key, member)
+                    .await
+                    .is_ok_and(|removed| removed > 0)
+            }
+            SessionStore::Memory(map) => {
+                let mut map = map.lock().unwrap();
+                let mut members = memory_members(&map, key);
+                let Some(index) = members.iter().position(|item| item == member)
+                else {
+                    return false;
+                };
+                members.remove(index);
+                if let Some((value, _)) = map.get_mut(key) {
+                    *value = serde_json::to_string(&members).unwrap();
+                }
+                true
+            }
+        }
+    }
+
+    async fn members(&mut self, key: &str) -> Vec<String> {
+        match &mut self.con {
+            SessionStore::Redis(con) => con
+                .smembers::<String, Vec<String>>(String::from(key))
+                .await
+                .unwrap_or_default(),
+            SessionStore::Memory(map) => memory_members(&map.lock().unwrap(), key),
+        }
+    }
+
+    async fn delete_value(&mut self, key: &str) {
+        match &mut self.con {
+            SessionStore::Redis(con) => {
+                let _ = con.del::<String, usize>(String::from(key)).await;
+            }
+            SessionStore::Memory(map) => {
+                map.lock().unwrap().remove(key);
+            }
+        }
+    }
+
+    /// Cached value, `None` if it's missing or expired.
+    pub async fn get_cached<T: DeserializeOwned>(&mut self, key: &str) -> Option<T> {
+        let value = self.get_value(key).await?;
+        serde_json::from_str(&value).ok()
+    }
+
+    /// Cache value for `ttl` seconds.
+    pub async fn set_cached<T: Serialize>(
+        &mut self,
+        key: &str,
+        value: &T,
+        ttl: usize,
+    ) {
+        let Ok(value) = serde_json::to_string(value) else {
+            return;
+        };
+        self.set_value(key, value, ttl).await;
     }
 
-    /// Get session if it exist.
-    pub async fn get_session(&mut self, key: &str) -> Option<UserSession> {
-        let s = self
-            .con
-            .get::<String, String>(String::from(key))
-            .await
-            .ok()?;
-        let value = serde_json::from_str::<UserSession>(&s).ok()?;
+    /// Get session if it exist, `now` is saved as last seen time.
+    pub async fn get_session(
+        &mut self,
+        key: &str,
+        now: DateTime,
+    ) -> Option<UserSession> {
+        let s = self.get_value(key).await?;
+        let mut value = serde_json::from_str::<UserSession>(&s).ok()?;
+        let now = now.timestamp_millis();
+        if now - value.last_seen > LAST_SEEN_INTERVAL {
+            value.last_seen = now;
+            // New session is saved below.
+            if !value.is_new {
+                self.update_session(key, &value).await;
+            }
+        }
         let value = self.set_session(key, value, false).await;
         Some(value)
     }
@@ -141,36 +348,80 @@ impl RedisCli {
             if !force_set {
                 value.not_new();
             }
-            let _ = self
-                .con
-                .set::<String, String, String>(
-                    String::from(key),
-                    serde_json::to_string(&value).unwrap(),
-                )
-                .await;
-            let _e = self
-                .con
-                .expire::<String, usize>(
-                    String::from(key),
-                    self.ttl_days(value.reg) as i64,
-                )
-                .await;
+            self.store_session(key, &value).await;
         }
         value
     }
 
+    async fn store_session(&mut self, key: &str, value: &UserSession) {
+        let ttl = self.ttl_days(value.reg);
+        self.set_value(key, serde_json::to_string(value).unwrap(), ttl)
+            .await;
+        self.add_member(&session_index(&value.username), key, ttl)
+            .await;
+    }
+
+    /// Save changed session without extending its expiry.
+    pub async fn update_session(&mut self, key: &str, value: &UserSession) {
+        self.replace_value(key, serde_json::to_string(value).unwrap())
+            .await;
+    }
+
+    /// Active sessions of player, latest seen first. Expired ones are removed
+    /// from index.
+    pub async fn user_sessions(&mut self, username: &str) -> Vec<UserSession> {
+        let index = session_index(username);
+        let mut sessions = vec![];
+        for key in self.members(&index).await {
+            let session = self
+                .get_value(&key)
+                .await
+                .and_then(|s| serde_json::from_str::<UserSession>(&s).ok());
+            match session {
+                // Session of guest is renamed after login.
+                Some(mut session) if session.username == username => {
+                    session.session = key;
+                    sessions.push(session);
+                }
+                _ => {
+                    self.remove_member(&index, &key).await;
+                }
+            }
+        }
+        sessions.sort_by_key(|session| {
+            (Reverse(session.last_seen), Reverse(session.created_at))
+        });
+        sessions
+    }
+
+    /// Delete session of player, returns `false` if it doesn't exist.
+    pub async fn delete_session(&mut self, username: &str, key: &str) -> bool {
+        if !self.remove_member(&session_index(username), key).await {
+            return false;
+        }
+        self.delete_value(key).await;
+        true
+    }
+
     /// Create session.
     pub async fn new_session(
         &mut self,
         players: &Collection<Player>,
         cookie_value: CookieValue,
+        now: DateTime,
     ) -> UserSession {
         let username = create_player(players).await;
         loop {
             let s = Session::new();
-            if (self.get_session(s.id()).await).is_none() {
-                let value =
-                    UserSession::new(&username, s.id(), false, "", cookie_value);
+            if (self.get_session(s.id(), now).await).is_none() {
+                let value = UserSession::new(
+                    &username,
+                    s.id(),
+                    false,
+                    "",
+                    cookie_value,
+                    now,
+                );
                 return self.set_session(s.id(), value, true).await;
             }
         }
@@ -182,10 +433,29 @@ impl RedisCli {
         if reg {
             return day * 365;
         }
-        day * 2
+        day * ANON_SESSION_DAYS as usize
     }
 }
 
+/// Session of anonymous player expires after this many days.
+pub const ANON_SESSION_DAYS: u32 = 2;
+
+/// Set with keys of all sessions of player.
+fn session_index(username: &str) -> String {
+    format!("sessions:{}", username)
+}
+
+/// Set is stored as JSON list in memory store.
+fn memory_members(
+    map: &HashMap<String, (String, Instant)>,
+    key: &str,
+) -> Vec<String> {
+    map.get(key)
+        .filter(|(_, expires_at)| *expires_at > Instant::now())
+        .and_then(|(value, _)| serde_json::from_str(value).ok())
+        .unwrap_or_default()
+}
+
 // #[async_trait]
 impl<S> FromRequestParts<S> for UserSession
 where
@@ -209,15 +479,16 @@ where
         let store = AppState::from_ref(state);
         let session_cookie = cookie.get(AXUM_SESSION_COOKIE_NAME);
         let mut redis = store.db.redis.clone();
+        let now = store.db.clock.bson_now();
 
         if let Some(session) = session_cookie {
-            if let Some(session) = redis.get_session(session).await {
+            if let Some(session) = redis.get_session(session, now).await {
                 return Ok(session);
             }
         }
 
         let session = redis
-            .new_session(&store.db.mongo.players, cookie_value)
+            .new_session(&store.db.mongo.players, cookie_value, now)
             .await;
         Ok(session)
     }
@@ -228,6 +499,8 @@ where
 pub struct VueUser {
     pub username: String,
     pub logged: bool,
+    /// Anonymous name whose games can be merged into this account.
+    pub merge_from: Option<String>,
 }
 
 impl From<&UserSession> for VueUser {
@@ -235,6 +508,30 @@ impl From<&UserSession> for VueUser {
         Self {
             username: String::from(&user.username),
             logged: user.reg,
+            merge_from: user.merge_from.clone(),
+        }
+    }
+}
+
+/// Session shown in account sessions list.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub created_at: i64,
+    pub last_seen: i64,
+    pub user_agent: String,
+    /// Session that made this request.
+    pub current: bool,
+}
+
+impl SessionInfo {
+    pub fn new(session: &UserSession, current: &UserSession) -> Self {
+        Self {
+            id: session.public_id(),
+            created_at: session.created_at,
+            last_seen: session.last_seen,
+            user_agent: String::from(&session.user_agent),
+            current: session.session == current.session,
         }
     }
 }
@@ -245,6 +542,8 @@ impl From<&UserSession> for Player {
             _id: String::from(&other.username),
             reg: other.reg,
             created_at: DateTime::now(),
+            roles: vec![],
+            blocked: vec![],
         }
     }
 }
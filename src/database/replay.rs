@@ -0,0 +1,222 @@
+use std::hash::Hash;
+
+use serde::{Deserialize, Serialize};
+use shuuro::{
+    Move, Square, Variant,
+    attacks::Attacks,
+    bitboard::BitBoard,
+    position::{Board, Outcome, Placement, Play, Rules, Sfen},
+    shuuro6::{attacks6::Attacks6, bitboard6::BB6, position6::P6, square6::Square6},
+    shuuro8::{attacks8::Attacks8, bitboard8::BB8, position8::P8, square8::Square8},
+    shuuro12::{
+        attacks12::Attacks12, bitboard12::BB12, position12::P12, square12::Square12,
+    },
+};
+use typeshare::typeshare;
+
+use super::{
+    clock::time_control::MoveTime,
+    model::{GameResult, GameStatus, ShuuroGame},
+};
+
+/// Position after one move of stored game.
+#[typeshare]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReplayPosition {
+    pub stage: u8,
+    /// Empty for starting position of stage.
+    pub game_move: String,
+    pub sfen: String,
+    /// Missing for starting positions and old games.
+    pub move_time: Option<MoveTime>,
+}
+
+#[typeshare]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GameReplay {
+    pub id: String,
+    pub players: [String; 2],
+    #[typeshare(serialized_as = "u8")]
+    pub variant: u8,
+    pub status: GameStatus,
+    pub result: GameResult,
+    pub positions: Vec<ReplayPosition>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", content = "content")]
+pub enum ReplayError {
+    InvalidSfen { stage: u8, sfen: String },
+    IllegalMove { stage: u8, ply: usize, game_move: String },
+}
+
+/// Result of replaying all moves.
+pub struct Replay {
+    pub positions: Vec<ReplayPosition>,
+    /// Status and result after last fight move, `None` if fight didn't start.
+    pub outcome: Option<(GameStatus, GameResult)>,
+}
+
+impl Replay {
+    pub fn last_sfen(&self) -> Option<&str> {
+        self.positions.last().map(|position| position.sfen.as_str())
+    }
+}
+
+impl GameReplay {
+    pub fn new(game: &ShuuroGame) -> Result<Self, ReplayError> {
+        Ok(Self {
+            id: game._id.clone(),
+            players: game.players.clone(),
+            variant: game.variant as u8,
+            status: game.status,
+            result: game.result,
+            positions: replay_game(game)?.positions,
+        })
+    }
+}
+
+/// Replay game with position type of its variant.
+pub fn replay_game(game: &ShuuroGame) -> Result<Replay, ReplayError> {
+    match game.variant {
+        Variant::Shuuro | Variant::ShuuroFairy => replay::<
+            Square12,
+            BB12<Square12>,
+            Attacks12<Square12, BB12<Square12>>,
+            P12<Square12, BB12<Square12>>,
+        >(game),
+        Variant::ShuuroMini | Variant::ShuuroMiniFairy => replay::<
+            Square6,
+            BB6<Square6>,
+            Attacks6<Square6, BB6<Square6>>,
+            P6<Square6, BB6<Square6>>,
+        >(game),
+        Variant::Standard | Variant::StandardFairy => replay::<
+            Square8,
+            BB8<Square8>,
+            Attacks8<Square8, BB8<Square8>>,
+            P8<Square8, BB8<Square8>>,
+        >(game),
+    }
+}
+
+/// Same steps as `game_task` when it restores unfinished game.
+pub fn replay<S, B, A, P>(game: &ShuuroGame) -> Result<Replay, ReplayError>
+where
+    S: Square + Hash,
+    B: BitBoard<S>,
+    A: Attacks<S, B>,
+    P: Sized
+        + Clone
+        + Board<S, B, A>
+        + Sfen<S, B, A>
+        + Placement<S, B, A>
+        + Play<S, B, A>
+        + Rules<S, B, A>,
+{
+    let mut positions = vec![];
+    let mut outcome = None;
+    if !game.placement_start.is_empty() {
+        let mut placement = P::new();
+        placement.update_variant(game.variant);
+        if placement.set_sfen(&game.placement_start).is_err() {
+            return Err(ReplayError::InvalidSfen {
+                stage: 1,
+                sfen: game.placement_start.clone(),
+            });
+        }
+        // Game without sub variant starts with selection.
+        let stage = match game.sub_variant {
+            Some(_) => 1,
+            None => 0,
+        };
+        positions.push(start(stage, &game.placement_start));
+        for (ply, game_move) in game.history.1.iter().enumerate() {
+            let illegal = || ReplayError::IllegalMove {
+                stage: 1,
+                ply,
+                game_move: game_move.clone(),
+            };
+            let Some(Move::Put { to, piece }) = Move::<S>::from_sfen(game_move)
+            else {
+                return Err(illegal());
+            };
+            let sfen = placement.place(piece, to).ok_or_else(illegal)?;
+            positions.push(ReplayPosition {
+                stage: 1,
+                game_move: game_move.clone(),
+                sfen: sfen.to_string(),
+                move_time: game.move_times.0.get(ply).copied(),
+            });
+        }
+    }
+    if !game.game_start.is_empty() {
+        let mut fight = P::new();
+        fight.update_variant(game.variant);
+        match fight.set_sfen(&game.game_start) {
+            Ok(first) => outcome = Some(outcome_status(&first, GameResult::Draw)),
+            Err(_) => {
+                return Err(ReplayError::InvalidSfen {
+                    stage: 2,
+                    sfen: game.game_start.clone(),
+                });
+            }
+        }
+        positions.push(start(2, &game.game_start));
+        for (ply, game_move) in game.history.2.iter().enumerate() {
+            let illegal = || ReplayError::IllegalMove {
+                stage: 2,
+                ply,
+                game_move: game_move.clone(),
+            };
+            let Some(m @ Move::Normal { .. }) = Move::<S>::from_sfen(game_move)
+            else {
+                return Err(illegal());
+            };
+            let result = fight.make_move(m).map_err(|_| illegal())?;
+            outcome = Some(outcome_status(&result, GameResult::Draw));
+            positions.push(ReplayPosition {
+                stage: 2,
+                game_move: game_move.clone(),
+                sfen: fight.generate_sfen(),
+                move_time: game.move_times.1.get(ply).copied(),
+            });
+        }
+    }
+    Ok(Replay {
+        positions,
+        outcome,
+    })
+}
+
+/// Status and result after move, `result` is kept while game is ongoing.
+pub fn outcome_status(
+    outcome: &Outcome,
+    result: GameResult,
+) -> (GameStatus, GameResult) {
+    match outcome {
+        Outcome::Check { color: _ } | Outcome::MoveOk => {
+            (GameStatus::Ongoing, result)
+        }
+        Outcome::MoveNotOk => (GameStatus::NotStarted, result),
+        Outcome::Stalemate => (GameStatus::Stalemate, GameResult::Draw),
+        Outcome::DrawByAgreement => (GameStatus::Agreement, GameResult::Draw),
+        Outcome::DrawByRepetition => (GameStatus::Repetition, GameResult::Draw),
+        Outcome::DrawByMaterial => (GameStatus::Material, GameResult::Draw),
+        Outcome::Checkmate { color } => (GameStatus::Checkmate, (*color).into()),
+        Outcome::Resign { color } => (GameStatus::Resign, (*color).into()),
+        Outcome::LostOnTime { color } => (GameStatus::Timeout, (*color).into()),
+        Outcome::FirstMoveError { color } => {
+            (GameStatus::FirstMoveError, (*color).into())
+        }
+    }
+}
+
+fn start(stage: u8, sfen: &str) -> ReplayPosition {
+    ReplayPosition {
+        stage,
+        game_move: String::new(),
+        sfen: String::from(sfen),
+        move_time: None,
+    }
+}
@@ -0,0 +1,99 @@
+use std::marker::PhantomData;
+
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use hyper::StatusCode;
+use mongodb::Collection;
+
+use crate::AppState;
+
+use super::{
+    clock::queries::{add_audit_entry, get_player, set_role},
+    model::{AuditAction, AuditEntry, Mongo, Player, Role},
+    redis::UserSession,
+};
+
+/// Role that extractor requires.
+pub trait RequiredRole {
+    const ROLE: Role;
+}
+
+pub struct AdminRole;
+
+impl RequiredRole for AdminRole {
+    const ROLE: Role = Role::Admin;
+}
+
+pub struct ModeratorRole;
+
+impl RequiredRole for ModeratorRole {
+    const ROLE: Role = Role::Moderator;
+}
+
+/// Current user, rejected with 403 if it doesn't have role `R`.
+pub struct WithRole<R: RequiredRole>(pub UserSession, PhantomData<R>);
+
+pub type Admin = WithRole<AdminRole>;
+pub type Moderator = WithRole<ModeratorRole>;
+
+impl<S, R> FromRequestParts<S> for WithRole<R>
+where
+    AppState: FromRef<S>,
+    S: Send + Sync + 'static,
+    R: RequiredRole + Send,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let user = UserSession::from_request_parts(parts, state).await?;
+        let store = AppState::from_ref(state);
+        if !has_role(&store.db.mongo.players, &user, R::ROLE).await {
+            return Err((StatusCode::FORBIDDEN, "forbidden"));
+        }
+        Ok(WithRole(user, PhantomData))
+    }
+}
+
+/// Roles are stored only for registered players.
+pub async fn has_role(
+    players: &Collection<Player>,
+    user: &UserSession,
+    role: Role,
+) -> bool {
+    if !user.reg {
+        return false;
+    }
+    let Some(player) = get_player(players, &user.username).await else {
+        return false;
+    };
+    player.roles.iter().any(|item| item.allows(role))
+}
+
+/// Grant or remove role, change is written to audit log.
+///
+/// Returns `None` if player is not found.
+pub async fn change_role(
+    mongo: &Mongo,
+    actor: &str,
+    target: &str,
+    role: Role,
+    grant: bool,
+    reason: Option<String>,
+) -> Option<bool> {
+    let changed = set_role(&mongo.players, target, role, grant).await?;
+    if changed {
+        let action = if grant {
+            AuditAction::GrantRole(role)
+        } else {
+            AuditAction::RevokeRole(role)
+        };
+        let entry = AuditEntry::new(actor, target, action, reason);
+        add_audit_entry(&mongo.audit, &entry).await;
+    }
+    Some(changed)
+}
@@ -89,6 +89,18 @@ where
     s.serialize_u8((*x as usize) as u8)
 }
 
+pub fn serialize_color<S>(x: &Color, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let color = match x {
+        Color::White => 0,
+        Color::Black => 1,
+        _ => 2,
+    };
+    s.serialize_u8(color)
+}
+
 pub fn deserialize_color<'de, D>(data: D) -> Result<Color, D::Error>
 where
     D: Deserializer<'de>,
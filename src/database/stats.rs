@@ -0,0 +1,332 @@
+use std::collections::BTreeMap;
+
+use bson::{doc, Document};
+use futures::TryStreamExt;
+use mongodb::Collection;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use typeshare::typeshare;
+
+use super::{
+    model::{GameResult, GameStatus, ShuuroGame},
+    Database,
+};
+
+/// How long stats are cached, in seconds.
+const STATS_TTL: usize = 60 * 10;
+/// Most bought hands per variant.
+const HANDS_LIMIT: usize = 5;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[typeshare]
+pub enum GameOutcome {
+    Win,
+    Loss,
+    Draw,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[typeshare]
+pub struct Score {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl Score {
+    pub(super) fn add(&mut self, outcome: GameOutcome, count: u32) {
+        match outcome {
+            GameOutcome::Win => self.wins += count,
+            GameOutcome::Loss => self.losses += count,
+            GameOutcome::Draw => self.draws += count,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct StatusScore {
+    pub status: GameStatus,
+    pub score: Score,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct HandCount {
+    pub hand: String,
+    pub count: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[typeshare]
+pub struct VariantStats {
+    pub variant: u8,
+    pub games: u32,
+    pub score: Score,
+    pub by_status: Vec<StatusScore>,
+    /// Score as white and as black.
+    pub by_color: [Score; 2],
+    /// Average number of moves in fight stage.
+    pub average_plies: f64,
+    pub hands: Vec<HandCount>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct ProfileStats {
+    pub username: String,
+    pub variants: Vec<VariantStats>,
+}
+
+#[derive(Deserialize)]
+struct OutcomeKey {
+    variant: u8,
+    status: GameStatus,
+    color: usize,
+    outcome: GameOutcome,
+}
+
+#[derive(Deserialize)]
+struct OutcomeRow {
+    _id: OutcomeKey,
+    count: u32,
+    plies: i64,
+}
+
+#[derive(Deserialize)]
+struct HandKey {
+    variant: u8,
+    hand: String,
+}
+
+#[derive(Deserialize)]
+struct HandRow {
+    _id: HandKey,
+    count: u32,
+}
+
+/// Stats for player, cached for ten minutes.
+pub async fn profile_stats(db: &Database, username: &str) -> ProfileStats {
+    let key = format!("stats:{}", username);
+    let mut redis = db.redis.clone();
+    if let Some(stats) = redis.get_cached::<ProfileStats>(&key).await {
+        return stats;
+    }
+    let games = &db.mongo.games;
+    let outcomes = aggregate::<OutcomeRow>(games, outcomes(username)).await;
+    let hands = aggregate::<HandRow>(games, hands(username)).await;
+    let stats = ProfileStats {
+        username: String::from(username),
+        variants: collect_stats(outcomes, hands),
+    };
+    redis.set_cached(&key, &stats, STATS_TTL).await;
+    stats
+}
+
+pub(super) async fn aggregate<T: DeserializeOwned>(
+    db: &Collection<ShuuroGame>,
+    pipeline: Vec<Document>,
+) -> Vec<T> {
+    let Ok(res) = db.aggregate(pipeline).await else {
+        return vec![];
+    };
+    let docs: Vec<Document> = res.try_collect().await.unwrap_or_else(|_| vec![]);
+    docs.into_iter()
+        .filter_map(|doc| bson::from_document(doc).ok())
+        .collect()
+}
+
+/// Finished games of player, with player's color and outcome.
+pub(super) fn finished_games(username: &str) -> Vec<Document> {
+    let mut pipeline = vec![doc! {"$match": {
+        "players": String::from(username),
+        "status": {"$in": GameStatus::decided_codes()}
+    }}];
+    pipeline.extend(outcome_fields(username));
+    pipeline
+}
+
+/// Adds player's `color` and `outcome` to each game.
+///
+/// After checkmate `result` is winner, after resign, timeout and first move
+/// error it's loser.
+pub(super) fn outcome_fields(username: &str) -> [Document; 2] {
+    let draw = GameResult::Draw as i32;
+    let draws = GameStatus::draw_codes();
+    let checkmate = GameStatus::Checkmate as i32;
+    [
+        doc! {"$addFields": {
+            "color": {"$indexOfArray": ["$players", String::from(username)]}
+        }},
+        doc! {"$addFields": {
+            "outcome": {"$switch": {
+                "branches": [
+                    {"case": {"$eq": ["$result", draw]}, "then": "draw"},
+                    {"case": {"$in": ["$status", draws]}, "then": "draw"},
+                    {"case": {"$eq": ["$status", checkmate]}, "then": {
+                        "$cond": [{"$eq": ["$result", "$color"]}, "win", "loss"]
+                    }}
+                ],
+                "default": {
+                    "$cond": [{"$eq": ["$result", "$color"]}, "loss", "win"]
+                }
+            }}
+        }},
+    ]
+}
+
+fn outcomes(username: &str) -> Vec<Document> {
+    let mut pipeline = finished_games(username);
+    pipeline.push(doc! {"$group": {
+        "_id": {
+            "variant": "$variant",
+            "status": "$status",
+            "color": "$color",
+            "outcome": "$outcome"
+        },
+        "count": {"$sum": 1},
+        "plies": {"$sum": {
+            "$size": {"$ifNull": [{"$arrayElemAt": ["$history", 2]}, []]}
+        }}
+    }});
+    pipeline
+}
+
+fn hands(username: &str) -> Vec<Document> {
+    let mut pipeline = finished_games(username);
+    pipeline.extend([
+        doc! {"$project": {
+            "variant": 1,
+            "hand": {"$arrayElemAt": ["$hands", "$color"]}
+        }},
+        doc! {"$match": {"hand": {"$nin": [null, ""]}}},
+        doc! {"$group": {
+            "_id": {"variant": "$variant", "hand": "$hand"},
+            "count": {"$sum": 1}
+        }},
+        doc! {"$sort": {"count": -1, "_id.hand": 1}},
+    ]);
+    pipeline
+}
+
+fn collect_stats(
+    outcomes: Vec<OutcomeRow>,
+    hands: Vec<HandRow>,
+) -> Vec<VariantStats> {
+    let mut variants: BTreeMap<u8, (VariantStats, i64)> = BTreeMap::new();
+    for row in outcomes {
+        let key = row._id;
+        let (stats, plies) = variants.entry(key.variant).or_default();
+        stats.variant = key.variant;
+        stats.games += row.count;
+        stats.score.add(key.outcome, row.count);
+        if let Some(color) = stats.by_color.get_mut(key.color) {
+            color.add(key.outcome, row.count);
+        }
+        let status = stats
+            .by_status
+            .iter_mut()
+            .find(|item| item.status == key.status);
+        match status {
+            Some(item) => item.score.add(key.outcome, row.count),
+            None => {
+                let mut score = Score::default();
+                score.add(key.outcome, row.count);
+                stats.by_status.push(StatusScore {
+                    status: key.status,
+                    score,
+                });
+            }
+        }
+        *plies += row.plies;
+    }
+    for row in hands {
+        let Some((stats, _)) = variants.get_mut(&row._id.variant) else {
+            continue;
+        };
+        if stats.hands.len() < HANDS_LIMIT {
+            stats.hands.push(HandCount {
+                hand: row._id.hand,
+                count: row.count,
+            });
+        }
+    }
+    variants
+        .into_values()
+        .map(|(mut stats, plies)| {
+            stats.by_status.sort_by_key(|item| item.status as i32);
+            if stats.games > 0 {
+                stats.average_plies = plies as f64 / stats.games as f64;
+            }
+            stats
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(
+        status: GameStatus,
+        color: usize,
+        outcome: GameOutcome,
+        count: u32,
+    ) -> OutcomeRow {
+        OutcomeRow {
+            _id: OutcomeKey {
+                variant: 2,
+                status,
+                color,
+                outcome,
+            },
+            count,
+            plies: count as i64 * 10,
+        }
+    }
+
+    #[test]
+    fn rows_are_merged_per_variant() {
+        let outcomes = vec![
+            row(GameStatus::Checkmate, 0, GameOutcome::Win, 2),
+            row(GameStatus::Resign, 1, GameOutcome::Loss, 1),
+            row(GameStatus::Repetition, 1, GameOutcome::Draw, 1),
+        ];
+        let hands = vec![HandRow {
+            _id: HandKey {
+                variant: 2,
+                hand: String::from("KQRR"),
+            },
+            count: 3,
+        }];
+        let stats = collect_stats(outcomes, hands);
+        assert_eq!(stats.len(), 1);
+        let stats = &stats[0];
+        assert_eq!(stats.games, 4);
+        assert_eq!(
+            stats.score,
+            Score {
+                wins: 2,
+                losses: 1,
+                draws: 1
+            }
+        );
+        assert_eq!(stats.by_color[0].wins, 2);
+        assert_eq!(stats.by_color[1].losses, 1);
+        assert_eq!(stats.by_status.len(), 3);
+        assert_eq!(stats.average_plies, 10.0);
+        assert_eq!(stats.hands[0].count, 3);
+    }
+
+    #[test]
+    fn hands_without_games_are_skipped() {
+        let hands = vec![HandRow {
+            _id: HandKey {
+                variant: 4,
+                hand: String::from("KQ"),
+            },
+            count: 1,
+        }];
+        assert!(collect_stats(vec![], hands).is_empty());
+    }
+}
@@ -0,0 +1,214 @@
+use bson::{doc, Document};
+use futures::TryStreamExt;
+use mongodb::{error::Error, Collection};
+use serde::Serialize;
+
+use super::{
+    model::{GameResult, GameStatus, ShuuroGame},
+    replay::{replay_game, ReplayError},
+};
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", content = "content")]
+pub enum Issue {
+    /// Document can't be read as `ShuuroGame`.
+    Unreadable { error: String },
+    Replay(ReplayError),
+    SfenMismatch { stored: String, replayed: String },
+    StatusMismatch {
+        stored: (GameStatus, GameResult),
+        replayed: Option<(GameStatus, GameResult)>,
+    },
+    Termination { stored: String, expected: String },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct GameIssues {
+    pub id: String,
+    pub issues: Vec<Issue>,
+    pub repaired: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct VerifyReport {
+    pub checked: u64,
+    pub repaired: u64,
+    /// Only games with issues.
+    pub games: Vec<GameIssues>,
+}
+
+/// Fields that can be computed again from moves.
+struct Derived {
+    sfen: Option<String>,
+    status: Option<(GameStatus, GameResult)>,
+}
+
+/// Replay every stored game. With `repair` derived fields of finished
+/// games are overwritten, games with illegal moves are never changed.
+pub async fn verify_games(
+    db: &Collection<ShuuroGame>,
+    repair: bool,
+) -> Result<VerifyReport, Error> {
+    let mut report = VerifyReport::default();
+    let raw = db.clone_with_type::<Document>();
+    let mut cursor = raw.find(doc! {}).await?;
+    while let Some(document) = cursor.try_next().await? {
+        report.checked += 1;
+        let id = match document.get("_id") {
+            Some(bson::Bson::String(id)) => id.clone(),
+            Some(id) => id.to_string(),
+            None => String::new(),
+        };
+        let game = match bson::from_document::<ShuuroGame>(document) {
+            Ok(game) => game,
+            Err(e) => {
+                let error = e.to_string();
+                report.games.push(GameIssues {
+                    id,
+                    issues: vec![Issue::Unreadable { error }],
+                    repaired: false,
+                });
+                continue;
+            }
+        };
+        let (issues, derived) = verify_game(&game);
+        if issues.is_empty() {
+            continue;
+        }
+        let repaired = match derived {
+            Some(derived) if repair && game.status.is_finished() => {
+                repair_game(db, &game, derived).await?
+            }
+            _ => false,
+        };
+        if repaired {
+            report.repaired += 1;
+        }
+        report.games.push(GameIssues {
+            id,
+            issues,
+            repaired,
+        });
+    }
+    Ok(report)
+}
+
+fn verify_game(game: &ShuuroGame) -> (Vec<Issue>, Option<Derived>) {
+    let mut issues = vec![];
+    let expected = game.status.termination();
+    if game.termination != expected {
+        issues.push(Issue::Termination {
+            stored: game.termination.clone(),
+            expected: String::from(expected),
+        });
+    }
+    let replay = match replay_game(game) {
+        Ok(replay) => replay,
+        Err(e) => {
+            issues.push(Issue::Replay(e));
+            return (issues, None);
+        }
+    };
+    let sfen = replay.last_sfen().map(String::from);
+    if let Some(replayed) = sfen.as_ref().filter(|sfen| **sfen != game.sfen) {
+        issues.push(Issue::SfenMismatch {
+            stored: game.sfen.clone(),
+            replayed: replayed.clone(),
+        });
+    }
+    let status = board_status(replay.outcome);
+    if let Some(issue) = status_issue((game.status, game.result), status) {
+        issues.push(issue);
+    }
+    (issues, Some(Derived { sfen, status }))
+}
+
+/// Game end that can be seen on board.
+fn board_status(
+    outcome: Option<(GameStatus, GameResult)>,
+) -> Option<(GameStatus, GameResult)> {
+    outcome.filter(|(status, _)| status.is_finished())
+}
+
+/// Position decides game end only for these statuses, others can happen in
+/// any position.
+fn status_issue(
+    stored: (GameStatus, GameResult),
+    board: Option<(GameStatus, GameResult)>,
+) -> Option<Issue> {
+    let from_board = matches!(
+        stored.0,
+        GameStatus::Checkmate
+            | GameStatus::Stalemate
+            | GameStatus::Repetition
+            | GameStatus::Material
+            | GameStatus::FirstMoveError
+    );
+    let contradicts = match board {
+        Some(board) => board != stored,
+        None => from_board,
+    };
+    contradicts.then_some(Issue::StatusMismatch {
+        stored,
+        replayed: board,
+    })
+}
+
+/// Returns `false` if derived fields already match stored ones.
+async fn repair_game(
+    db: &Collection<ShuuroGame>,
+    game: &ShuuroGame,
+    derived: Derived,
+) -> Result<bool, Error> {
+    let mut repaired = game.clone();
+    if let Some(sfen) = derived.sfen {
+        repaired.sfen = sfen;
+    }
+    let (status, result) = derived.status.unwrap_or((game.status, game.result));
+    repaired.set_status(status, result);
+    if !changed(game, &repaired) {
+        return Ok(false);
+    }
+    let update = doc! {"$set": {
+        "sfen": &repaired.sfen,
+        "status": status as i32,
+        "result": result as i32,
+        "termination": &repaired.termination
+    }};
+    let res = db.update_one(doc! {"_id": &game._id}, update).await?;
+    Ok(res.modified_count == 1)
+}
+
+fn changed(stored: &ShuuroGame, repaired: &ShuuroGame) -> bool {
+    stored.sfen != repaired.sfen
+        || stored.status != repaired.status
+        || stored.result != repaired.result
+        || stored.termination != repaired.termination
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resign_in_any_position_is_fine() {
+        let stored = (GameStatus::Resign, GameResult::White);
+        assert_eq!(status_issue(stored, None), None);
+    }
+
+    #[test]
+    fn checkmate_must_be_on_board() {
+        let stored = (GameStatus::Checkmate, GameResult::Black);
+        assert!(status_issue(stored, None).is_some());
+        assert_eq!(status_issue(stored, Some(stored)), None);
+        let board = (GameStatus::Checkmate, GameResult::White);
+        assert!(status_issue(stored, Some(board)).is_some());
+    }
+
+    #[test]
+    fn finished_board_contradicts_other_status() {
+        let stored = (GameStatus::Timeout, GameResult::White);
+        let board = (GameStatus::Stalemate, GameResult::Draw);
+        assert!(status_issue(stored, Some(board)).is_some());
+    }
+}
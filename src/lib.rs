@@ -0,0 +1,125 @@
+pub mod config;
+pub mod database;
+pub mod lichess;
+pub mod routes;
+pub mod websockets;
+
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    http::HeaderValue,
+    routing::{delete, get, post},
+    Router,
+};
+use config::Config;
+use database::Database;
+use minijinja::Environment;
+use routes::{
+    add_sanction, audit_log, block_player, callback, cleanup_report, decline_merge,
+    end_game, follow_player, game_axum, game_vue, games_axum, games_vue, home,
+    how_to_play, lift_sanction, logged, login, logout, merge_anon, player_sanctions,
+    read_notifications, revoke_session, save_state, tv, unblock_player,
+    unfollow_player, update_role, vue_blocked, vue_challenges, vue_crosstable,
+    vue_following, vue_history, vue_merge, vue_notifications, vue_replay,
+    vue_sessions, vue_stats, vue_unread_notifications, vue_user,
+};
+use tower_http::cors::CorsLayer;
+use tower_http::services::ServeDir;
+
+use websockets::{channels::WsState, handler::websocket_handler};
+
+/// Build router with all routes and static assets.
+pub fn app(state: AppState) -> Router {
+    let cors = cors(&state.config.frontend_url);
+    Router::new()
+        .route("/login", get(login))
+        .route("/logout", post(logout))
+        .route("/", get(home))
+        .route("/game/{id}", get(game_axum))
+        .route("/how-to-play-shuuro", get(how_to_play))
+        .route("/tv", get(tv))
+        .route("/@/{username}", get(games_axum))
+        .route("/callback", get(callback))
+        .route("/logged", get(logged))
+        .route("/vue_user", get(vue_user))
+        .route("/vue/sessions", get(vue_sessions))
+        .route("/vue/sessions/{id}", delete(revoke_session))
+        .route(
+            "/vue/merge",
+            get(vue_merge).post(merge_anon).delete(decline_merge),
+        )
+        .route("/vue/challenges", get(vue_challenges))
+        .route("/vue/notifications", get(vue_notifications))
+        .route("/vue/notifications/unread", get(vue_unread_notifications))
+        .route("/vue/notifications/read", post(read_notifications))
+        .route("/vue/following", get(vue_following))
+        .route(
+            "/vue/follow/{username}",
+            post(follow_player).delete(unfollow_player),
+        )
+        .route("/vue/blocked", get(vue_blocked))
+        .route(
+            "/vue/block/{username}",
+            post(block_player).delete(unblock_player),
+        )
+        .route("/vue/game/{id}", get(game_vue))
+        .route("/vue/replay/{id}", get(vue_replay))
+        .route("/vue/@/{username}/{page}", get(games_vue))
+        .route("/vue/history/{username}", get(vue_history))
+        .route("/vue/stats/{username}", get(vue_stats))
+        .route("/vue/crosstable/{player}/{opponent}", get(vue_crosstable))
+        .route("/ws/", get(websocket_handler))
+        .route("/shutdown", get(save_state))
+        .route("/admin/roles", post(update_role))
+        .route("/admin/audit", get(audit_log))
+        .route("/admin/games/{id}/end", post(end_game))
+        .route("/admin/cleanup", get(cleanup_report))
+        .route("/mod/sanctions", post(add_sanction))
+        .route("/mod/sanctions/lift", post(lift_sanction))
+        .route("/mod/sanctions/{username}", get(player_sanctions))
+        .with_state(state)
+        .nest_service("/assets", ServeDir::new("./assets/assets"))
+        .nest_service("/board", ServeDir::new("./assets/board"))
+        .nest_service("/fonts", ServeDir::new("./assets/fonts"))
+        .nest_service("/images", ServeDir::new("./assets/images"))
+        .nest_service("/pieces", ServeDir::new("./assets/pieces"))
+        .layer(cors)
+}
+
+fn cors(frontend_url: &str) -> CorsLayer {
+    let cors = CorsLayer::new();
+    cors.allow_origin(frontend_url.parse::<HeaderValue>().unwrap())
+        .allow_credentials(true)
+}
+
+pub fn arc2<T>(data: T) -> Arc<Mutex<T>> {
+    Arc::new(Mutex::new(data))
+}
+
+#[derive(Clone)]
+pub struct AppState {
+    pub db: Arc<Database>,
+    pub ws: Arc<WsState>,
+    pub jinja: Arc<Environment<'static>>,
+    pub config: Arc<Config>,
+}
+
+impl AppState {
+    pub fn new(db: Arc<Database>, ws: Arc<WsState>, config: Arc<Config>) -> Self {
+        let mut jinja = Environment::new();
+        jinja
+            .add_template_owned(
+                "index.j2",
+                std::fs::read_to_string("./assets/index.j2").unwrap(),
+            )
+            .unwrap();
+
+        let jinja = Arc::new(jinja);
+        Self {
+            db,
+            ws,
+            jinja,
+            config,
+        }
+    }
+}
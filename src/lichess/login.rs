@@ -5,19 +5,18 @@ use reqwest::{Client, Url};
 use crate::lichess::login_helpers::base64_encode;
 
 use super::{
-    curr_url,
     login_helpers::{create_challenge, create_verifier},
     LoginData, PostLoginToken, Token,
 };
 use rand::Rng;
 
 /// Start of login process.
-pub fn login_url(login_state: &str, prod: bool) -> (Url, String) {
+pub fn login_url(login_state: &str, server_url: &str) -> (Url, String) {
     let url = "https://lichess.org/oauth?";
     let verifier: String = create_verifier();
     let challenge: String = create_challenge(&verifier);
     let mut final_url = Url::parse(url).unwrap();
-    let r = format!("{}/callback", curr_url(prod).0);
+    let r = format!("{}/callback", server_url);
 
     let queries = [
         ("state", login_state),
@@ -49,11 +48,11 @@ pub fn random_username() -> String {
 pub async fn get_lichess_token(
     code: &String,
     code_verifier: &String,
-    prod: bool,
+    server_url: &str,
 ) -> Result<Token, LichessError> {
     let url = "https://lichess.org/api/token";
     let body = PostLoginToken::new(code_verifier, code);
-    let body = body.to_json(prod);
+    let body = body.to_json(server_url);
     let client = Client::default();
     let req = client
         .post(url)
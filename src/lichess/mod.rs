@@ -1,9 +1,7 @@
-use std::env;
-
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::database::redis::CookieValue;
+use crate::{config::Config, database::redis::CookieValue};
 
 pub mod login;
 pub mod login_helpers;
@@ -25,9 +23,8 @@ impl PostLoginToken {
     }
 
     /// Function used to post.
-    pub fn to_json(&self, prod: bool) -> Value {
-        let uri = curr_url(prod);
-        let uri = format!("{}/callback", uri.0);
+    pub fn to_json(&self, server_url: &str) -> Value {
+        let uri = format!("{}/callback", server_url);
 
         serde_json::json!({
             "grant_type": "authorization_code",
@@ -58,33 +55,19 @@ pub struct LoginData {
 pub struct MyKey {
     pub prod: bool,
     pub login_state: String,
+    pub server_url: String,
 }
 
-impl Default for MyKey {
-    fn default() -> Self {
-        if let Ok(login_state) = env::var("LOGIN_STATE") {
-            if let Ok(prod) = env::var("PROD") {
-                if let Ok(prod) = prod.parse::<bool>() {
-                    return MyKey { prod, login_state };
-                }
-            }
-        }
+impl From<&Config> for MyKey {
+    fn from(config: &Config) -> Self {
         MyKey {
-            prod: false,
-            login_state: String::from(""),
+            prod: config.prod,
+            login_state: String::from(&config.login_state),
+            server_url: String::from(&config.server_url),
         }
     }
 }
 
-/// My server url.
-pub fn curr_url(prod: bool) -> (&'static str, &'static str) {
-    if prod {
-        ("https://lishuuro.org", "https://lishuuro.org")
-    } else {
-        ("http://localhost:3000", "http://localhost:3000")
-    }
-}
-
 pub fn cookies(prod: bool) -> CookieValue {
     if prod {
         CookieValue::new("None", "true", "true")
@@ -7,20 +7,45 @@ use axum::{
     Json,
 };
 use hyper::{HeaderMap, StatusCode};
+use bson::oid::ObjectId;
 use minijinja::context;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use shuuro::Color;
 use tokio::sync::oneshot;
 
 use crate::{
     database::{
-        clock::queries::{get_game_db, get_player, get_player_games, player_exist},
-        model::{Player, ShuuroGame},
-        redis::{UserSession, VueUser},
+        cleanup::{last_cleanup, CleanupReport},
+        clock::queries::{
+            follow, get_audit_log, get_following, get_game_db, get_notifications,
+            get_player, get_player_games, get_sanctions, mark_notifications_read,
+            pending_challenges, player_exist, set_blocked, unfollow,
+            unread_notifications,
+        },
+        crosstable::{crosstable, Crosstable, CROSSTABLE_GAMES_LIMIT},
+        history::{player_history, GameHistory, HistoryQuery},
+        merge::{merge_games, merge_offer, MergeOffer},
+        model::{
+            AuditEntry, Challenge, GameStatus, Notification, Player, Role,
+            Sanction, SanctionKind, ShuuroGame,
+        },
+        moderation::{lift_player_sanction, sanction_player},
+        notifications::push_unread_count,
+        stats::{profile_stats, ProfileStats},
+        redis::{SessionInfo, UserSession, VueUser},
+        replay::GameReplay,
+        roles::{change_role, Admin, Moderator, WithRole},
+    },
+    lichess::{
+        cookies,
+        login::{get_lichess_token, get_lichess_user, login_url, LichessError},
+    },
+    websockets::channels::{
+        game::GameMessage,
+        game_requests::GameRequestMessage,
+        games::GamesMessage,
+        players::{PlayerPresence, PlayersMessage},
     },
-    lichess::login::{get_lichess_token, get_lichess_user, login_url, LichessError},
-    prod_url,
-    websockets::channels::games::GamesMessage,
     AppState,
 };
 
@@ -29,7 +54,8 @@ pub async fn home(
     State(state): State<AppState>,
 ) -> Result<Html<String>, StatusCode> {
     let template = state.jinja.get_template("index.j2").unwrap();
-    let ctx = context!( description => "Play shuuro", title => "Home - lishuuro.org", props => "{}");
+    let title = page_title(&state, "Home");
+    let ctx = context!( description => "Play shuuro", title => title, props => "{}");
     let output = template.render(ctx).unwrap();
     Ok(Html(output))
 }
@@ -39,7 +65,8 @@ pub async fn tv(
     State(state): State<AppState>,
 ) -> Result<Html<String>, StatusCode> {
     let template = state.jinja.get_template("index.j2").unwrap();
-    let ctx = context!( description => "Watch TV", title => "Watch TV - lishuuro.org", props => "{}");
+    let title = page_title(&state, "Watch TV");
+    let ctx = context!( description => "Watch TV", title => title, props => "{}");
     let output = template.render(ctx).unwrap();
     Ok(Html(output))
 }
@@ -49,7 +76,9 @@ pub async fn how_to_play(
     State(state): State<AppState>,
 ) -> Result<Html<String>, StatusCode> {
     let template = state.jinja.get_template("index.j2").unwrap();
-    let ctx = context!( description => "How to play shuuro?", title => "How to play shuuro?- lishuuro.org", props => "{}");
+    let description = "How to play shuuro?";
+    let title = page_title(&state, description);
+    let ctx = context!( description => description, title => title, props => "{}");
     let output = template.render(ctx).unwrap();
     Ok(Html(output))
 }
@@ -60,7 +89,7 @@ pub async fn login(
 ) -> Redirect {
     let key = &state.db.key;
     let mut redis = state.db.redis.clone();
-    let url = login_url(&key.login_state, key.prod);
+    let url = login_url(&key.login_state, &key.server_url);
     user.code_verifier = url.1;
     redis.set_session(&user.session, user.clone(), true).await;
     Redirect::permanent(url.0.as_str())
@@ -74,31 +103,93 @@ pub async fn callback(
     let key = &state.db.key;
     let mongo = &state.db.mongo;
     let mut redis = state.db.redis.clone();
-    let r = prod_url(key.prod);
-    let r = format!("{}/logged", r.1);
+    let r = format!("{}/logged", &state.config.frontend_url);
     let Some(code) = params.get(&String::from("code")) else {
         return Ok(Redirect::permanent(r.as_str()));
     };
     let lichess_token =
-        get_lichess_token(code, &user.code_verifier, key.prod).await?;
+        get_lichess_token(code, &user.code_verifier, &key.server_url).await?;
 
     let lichess_user = get_lichess_user(lichess_token.access_token).await?;
-    let player = player_exist(&mongo.players, &lichess_user, &user).await;
-    if let Some(player) = player {
-        let session = String::from(&player.session);
-        redis.set_session(&session, player, true).await;
+    let Some(mut player) = player_exist(&mongo.players, &lichess_user, &user).await
+    else {
+        return Ok(Redirect::permanent(r.as_str()));
+    };
+    // Games played as guest are merged only if player accepts.
+    if !user.reg {
+        let offer =
+            merge_offer(&mongo.games, &user.username, &player.username).await;
+        player.merge_from = offer.map(|offer| offer.from);
     }
+    let session = String::from(&player.session);
+    redis.set_session(&session, player, true).await;
 
     Ok(Redirect::permanent(r.as_str()))
 }
 
+/// Destroy current session and start new anonymous one.
+pub async fn logout(
+    user: UserSession,
+    State(state): State<AppState>,
+) -> (HeaderMap, Redirect) {
+    let mut redis = state.db.redis.clone();
+    redis.delete_session(&user.username, &user.session).await;
+    let _ = state
+        .ws
+        .players
+        .send(PlayersMessage::CloseSession(String::from(&user.session)))
+        .await;
+    let cookie_value = cookies(state.db.key.prod);
+    let now = state.db.clock.bson_now();
+    let session = redis
+        .new_session(&state.db.mongo.players, cookie_value, now)
+        .await;
+    let r = format!("{}/", &state.config.frontend_url);
+    (session.headers(), Redirect::to(r.as_str()))
+}
+
+/// Active sessions of current player.
+pub async fn vue_sessions(
+    user: UserSession,
+    State(state): State<AppState>,
+) -> Json<Vec<SessionInfo>> {
+    let mut redis = state.db.redis.clone();
+    let sessions = redis.user_sessions(&user.username).await;
+    let sessions = sessions
+        .iter()
+        .map(|session| SessionInfo::new(session, &user))
+        .collect();
+    Json(sessions)
+}
+
+/// Revoke session by its public id, its sockets are closed.
+pub async fn revoke_session(
+    user: UserSession,
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> StatusCode {
+    let mut redis = state.db.redis.clone();
+    let sessions = redis.user_sessions(&user.username).await;
+    let Some(session) = sessions.iter().find(|session| session.public_id() == id)
+    else {
+        return StatusCode::NOT_FOUND;
+    };
+    redis.delete_session(&user.username, &session.session).await;
+    let _ = state
+        .ws
+        .players
+        .send(PlayersMessage::CloseSession(String::from(&session.session)))
+        .await;
+    StatusCode::OK
+}
+
 pub async fn logged(
     Path(username): Path<String>,
     State(state): State<AppState>,
 ) -> Result<Html<String>, StatusCode> {
     let template = state.jinja.get_template("index.j2").unwrap();
     let description = "Lichess account verified";
-    let title = format!("Player {} verified - lishuuro.org", &username);
+    let title = page_title(&state, &format!("Player {} verified", &username));
     let ctx = context!( description => description, title => title, props => "{}");
     let output = template.render(ctx).unwrap();
     Ok(Html(output))
@@ -108,13 +199,233 @@ pub async fn vue_user(user: UserSession) -> (HeaderMap, Json<VueUser>) {
     (headers, Json(VueUser::from(&user)))
 }
 
+/// Guest games that can be merged into account after login.
+pub async fn vue_merge(
+    user: UserSession,
+    State(state): State<AppState>,
+) -> Json<Option<MergeOffer>> {
+    let Some(from) = &user.merge_from else {
+        return Json(None);
+    };
+    Json(merge_offer(&state.db.mongo.games, from, &user.username).await)
+}
+
+/// Move guest games to account, live games are renamed too.
+pub async fn merge_anon(
+    mut user: UserSession,
+    State(state): State<AppState>,
+) -> Result<Json<u64>, StatusCode> {
+    let Some(from) = user.merge_from.take() else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let mut redis = state.db.redis.clone();
+    let session = String::from(&user.session);
+    redis.update_session(&session, &user).await;
+    let _ = state
+        .ws
+        .games
+        .send(GamesMessage::RenamePlayer {
+            from: from.clone(),
+            to: user.username.clone(),
+        })
+        .await;
+    let _ = state
+        .ws
+        .players
+        .send(PlayersMessage::RenamePlayer {
+            from: from.clone(),
+            to: user.username.clone(),
+        })
+        .await;
+    let _ = state
+        .ws
+        .game_requests
+        .send(GameRequestMessage::RenamePlayer {
+            old: from.clone(),
+            new: user.username.clone(),
+        })
+        .await;
+    let merged = merge_games(&state.db.mongo.games, &from, &user.username).await;
+    Ok(Json(merged))
+}
+
+/// Keep guest games under anonymous name.
+pub async fn decline_merge(
+    mut user: UserSession,
+    State(state): State<AppState>,
+) -> StatusCode {
+    if user.merge_from.take().is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+    let mut redis = state.db.redis.clone();
+    let session = String::from(&user.session);
+    redis.update_session(&session, &user).await;
+    StatusCode::OK
+}
+
+/// Pending challenges for current player, sent and received.
+pub async fn vue_challenges(
+    user: UserSession,
+    State(state): State<AppState>,
+) -> Json<Vec<Challenge>> {
+    let now = state.db.clock.bson_now();
+    Json(pending_challenges(&state.db.mongo.challenges, &user.username, now).await)
+}
+
+/// Latest notifications, `?unread=true` returns only unread ones.
+pub async fn vue_notifications(
+    user: UserSession,
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+) -> Json<Vec<Notification>> {
+    let unread_only = params.get("unread").is_some_and(|unread| unread == "true");
+    let notifications = &state.db.mongo.notifications;
+    Json(get_notifications(notifications, &user.username, unread_only, 50).await)
+}
+
+pub async fn vue_unread_notifications(
+    user: UserSession,
+    State(state): State<AppState>,
+) -> Json<u64> {
+    Json(unread_notifications(&state.db.mongo.notifications, &user.username).await)
+}
+
+/// Marks notifications as read and returns new unread count.
+pub async fn read_notifications(
+    user: UserSession,
+    State(state): State<AppState>,
+    Json(request): Json<ReadNotifications>,
+) -> Result<Json<u64>, StatusCode> {
+    let ids = match request.ids {
+        Some(ids) => Some(
+            ids.iter()
+                .map(ObjectId::parse_str)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| StatusCode::BAD_REQUEST)?,
+        ),
+        None => None,
+    };
+    let notifications = &state.db.mongo.notifications;
+    mark_notifications_read(notifications, &user.username, ids).await;
+    push_unread_count(&state.db, &state.ws.players, &user.username).await;
+    Ok(Json(unread_notifications(notifications, &user.username).await))
+}
+
+pub async fn follow_player(
+    user: UserSession,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> StatusCode {
+    if username == user.username {
+        return StatusCode::BAD_REQUEST;
+    }
+    if get_player(&state.db.mongo.players, &username).await.is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+    let now = state.db.clock.bson_now();
+    follow(&state.db.mongo.follows, &user.username, &username, now).await;
+    StatusCode::OK
+}
+
+pub async fn unfollow_player(
+    user: UserSession,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> StatusCode {
+    match unfollow(&state.db.mongo.follows, &user.username, &username).await {
+        true => StatusCode::OK,
+        false => StatusCode::NOT_FOUND,
+    }
+}
+
+pub async fn block_player(
+    user: UserSession,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> StatusCode {
+    if username == user.username {
+        return StatusCode::BAD_REQUEST;
+    }
+    if get_player(&state.db.mongo.players, &username).await.is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+    let players = &state.db.mongo.players;
+    match set_blocked(players, &user.username, &username, true).await {
+        Some(_) => StatusCode::OK,
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+pub async fn unblock_player(
+    user: UserSession,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> StatusCode {
+    let players = &state.db.mongo.players;
+    match set_blocked(players, &user.username, &username, false).await {
+        Some(true) => StatusCode::OK,
+        _ => StatusCode::NOT_FOUND,
+    }
+}
+
+pub async fn vue_blocked(
+    user: UserSession,
+    State(state): State<AppState>,
+) -> Json<Vec<String>> {
+    let player = get_player(&state.db.mongo.players, &user.username).await;
+    Json(player.map(|player| player.blocked).unwrap_or_default())
+}
+
+/// Record between two players, `?limit=` sets number of latest games.
+pub async fn vue_crosstable(
+    Path((player, opponent)): Path<(String, String)>,
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+) -> Json<Crosstable> {
+    let limit = params
+        .get("limit")
+        .and_then(|limit| limit.parse::<usize>().ok())
+        .unwrap_or(10)
+        .min(CROSSTABLE_GAMES_LIMIT);
+    let games = &state.db.mongo.games;
+    Json(crosstable(games, &player, &opponent, limit).await)
+}
+
+pub async fn vue_stats(
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<ProfileStats>, StatusCode> {
+    if get_player(&state.db.mongo.players, &username).await.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(profile_stats(&state.db, &username).await))
+}
+
+/// Followed players with their online status and current game.
+pub async fn vue_following(
+    user: UserSession,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<PlayerPresence>>, StatusCode> {
+    let players = get_following(&state.db.mongo.follows, &user.username).await;
+    let (sender, receiver) = oneshot::channel();
+    let _ = state
+        .ws
+        .players
+        .send(PlayersMessage::GetPresence { players, sender })
+        .await;
+    match receiver.await {
+        Ok(presence) => Ok(Json(presence)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
 pub async fn games_axum(
     Path(username): Path<String>,
     State(state): State<AppState>,
 ) -> Result<Html<String>, StatusCode> {
     let template = state.jinja.get_template("index.j2").unwrap();
     let description = format!("Check profile for {}", &username);
-    let title = format!("{} profile - lishuuro.org", &username);
+    let title = page_title(&state, &format!("{} profile", &username));
     let ctx = context!( description => description, title => title, props => "{}");
     let output = template.render(ctx).unwrap();
     Ok(Html(output))
@@ -127,6 +438,18 @@ pub async fn games_vue(
     Json(get_games(username, page, state).await)
 }
 
+/// Filtered history of player, next page is requested with `cursor`.
+pub async fn vue_history(
+    Path(username): Path<String>,
+    Query(query): Query<HistoryQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<GameHistory>, StatusCode> {
+    player_history(&state.db.mongo.games, &username, &query)
+        .await
+        .map(Json)
+        .ok_or(StatusCode::BAD_REQUEST)
+}
+
 pub async fn get_games(
     username: String,
     page: u64,
@@ -140,6 +463,11 @@ pub async fn get_games(
     UserProfileGames { player, games }
 }
 
+/// Title of server rendered page, followed by site name.
+fn page_title(state: &AppState, title: &str) -> String {
+    format!("{} - {}", title, state.config.site_name)
+}
+
 async fn get_game(game: String, state: AppState) -> Option<ShuuroGame> {
     let (tx, rx) = oneshot::channel();
     let _ = state
@@ -173,6 +501,20 @@ pub async fn game_vue(
     }
 }
 
+/// Every position of finished game.
+pub async fn vue_replay(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<GameReplay>, StatusCode> {
+    let game = get_game_db(&state.db.mongo.games, &id).await;
+    let Some(game) = game.filter(|game| game.status.is_finished()) else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    GameReplay::new(&game)
+        .map(Json)
+        .map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)
+}
+
 pub async fn game_axum(
     mut _user: UserSession,
     Path(id): Path<String>,
@@ -186,25 +528,203 @@ pub async fn game_axum(
         match game.players.iter().position(|player| player == "") {
             Some(index) => {
                 let name = &game.players[Color::from(index).flip() as usize];
-                format!("{} is waiting for you.. - lishuuro.org", name)
+                format!("{} is waiting for you..", name)
             }
-            None => format!(
-                "{} vs {} - lishuuro.org",
-                &game.players[0], &game.players[1]
-            ),
+            None => format!("{} vs {}", &game.players[0], &game.players[1]),
+        }
+    };
+    let message = page_title(&state, &message);
+    // Record of players at the board, only when both are known.
+    let table = match game.players.iter().any(|player| player.is_empty()) {
+        true => None,
+        false => {
+            let [player, opponent] = &game.players;
+            let games = &state.db.mongo.games;
+            Some(crosstable(games, player, opponent, 10).await)
         }
     };
-    let ctx = context!( description => &message, title => &message, props => game);
+    let ctx = context!(
+        description => &message,
+        title => &message,
+        props => game,
+        crosstable => table
+    );
     let output = template.render(ctx).unwrap();
     Ok(Html(output))
 }
 
-pub async fn save_state(user: UserSession, State(state): State<AppState>) {
-    if user.username == "iiiurosiii" {
-        let _ = state.ws.games.send(GamesMessage::SaveState).await;
+pub async fn save_state(_admin: Admin, State(state): State<AppState>) {
+    let _ = state.ws.games.send(GamesMessage::SaveState).await;
+}
+
+/// Ends live game. Games that server doesn't run are changed with
+/// `lishuuro-admin end`.
+pub async fn end_game(
+    _admin: Admin,
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Json(end): Json<EndGame>,
+) -> StatusCode {
+    let Ok((status, result)) =
+        GameStatus::admin_end(end.status.into(), end.result.map(i64::from))
+    else {
+        return StatusCode::BAD_REQUEST;
+    };
+    let (sender, receiver) = oneshot::channel();
+    let _ = state
+        .ws
+        .games
+        .send(GamesMessage::GetChannel { sender, id })
+        .await;
+    let Ok(game) = receiver.await else {
+        return StatusCode::NOT_FOUND;
+    };
+    match game.send(GameMessage::End { status, result }).await {
+        Ok(_) => StatusCode::OK,
+        Err(_) => StatusCode::NOT_FOUND,
+    }
+}
+
+pub async fn update_role(
+    WithRole(admin, _): Admin,
+    State(state): State<AppState>,
+    Json(change): Json<RoleChange>,
+) -> StatusCode {
+    let changed = change_role(
+        &state.db.mongo,
+        &admin.username,
+        &change.username,
+        change.role,
+        change.grant,
+        change.reason,
+    )
+    .await;
+    match changed {
+        Some(_) => StatusCode::OK,
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+pub async fn audit_log(
+    _admin: Admin,
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+) -> Json<Vec<AuditEntry>> {
+    let target = params.get("username").map(|username| username.as_str());
+    Json(get_audit_log(&state.db.mongo.audit, target, 100).await)
+}
+
+pub async fn cleanup_report(
+    _admin: Admin,
+    State(state): State<AppState>,
+) -> Result<Json<CleanupReport>, StatusCode> {
+    match last_cleanup(&state.db.redis).await {
+        Some(report) => Ok(Json(report)),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+pub async fn add_sanction(
+    WithRole(moderator, _): Moderator,
+    State(state): State<AppState>,
+    Json(request): Json<SanctionRequest>,
+) -> Result<Json<Sanction>, StatusCode> {
+    if request.reason.trim().is_empty() || request.minutes == Some(0) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let sanction = sanction_player(
+        &state.db.mongo,
+        &moderator.username,
+        &request.username,
+        request.kind,
+        &request.reason,
+        request.minutes,
+        state.db.clock.bson_now(),
+    )
+    .await;
+    if sanction.is_some() {
+        sanctions_changed(&state, &request.username).await;
+    }
+    match sanction {
+        Some(sanction) => Ok(Json(sanction)),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+pub async fn lift_sanction(
+    WithRole(moderator, _): Moderator,
+    State(state): State<AppState>,
+    Json(request): Json<SanctionRequest>,
+) -> StatusCode {
+    let lifted = lift_player_sanction(
+        &state.db.mongo,
+        &moderator.username,
+        &request.username,
+        request.kind,
+        &request.reason,
+        state.db.clock.bson_now(),
+    )
+    .await;
+    if lifted {
+        sanctions_changed(&state, &request.username).await;
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
     }
 }
 
+/// Drop sanctions cached for chat, so change applies to next message.
+async fn sanctions_changed(state: &AppState, username: &str) {
+    let player = String::from(username);
+    let _ = state
+        .ws
+        .players
+        .send(PlayersMessage::SanctionsChanged(player))
+        .await;
+}
+
+pub async fn player_sanctions(
+    _moderator: Moderator,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> Json<Vec<Sanction>> {
+    Json(get_sanctions(&state.db.mongo.sanctions, &username).await)
+}
+
+#[derive(Deserialize)]
+#[typeshare]
+pub struct ReadNotifications {
+    /// All notifications are marked if missing or empty.
+    ids: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[typeshare]
+pub struct SanctionRequest {
+    username: String,
+    kind: SanctionKind,
+    reason: String,
+    /// Permanent if empty, otherwise at least one minute.
+    minutes: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[typeshare]
+pub struct EndGame {
+    status: i32,
+    /// Required when status has winner.
+    result: Option<i32>,
+}
+
+#[derive(Deserialize)]
+#[typeshare]
+pub struct RoleChange {
+    username: String,
+    role: Role,
+    grant: bool,
+    reason: Option<String>,
+}
+
 #[derive(Serialize)]
 #[typeshare]
 pub struct UserProfileGames {
@@ -6,13 +6,16 @@ use shuuro::{
     position::{Board, Outcome, Placement, Play, Rules, Sfen},
 };
 use shuuro_engine::{Engine, engine::EngineDefs};
-use std::{env, f32::INFINITY, hash::Hash, marker::PhantomData, sync::Arc};
+use std::{f32::INFINITY, hash::Hash, marker::PhantomData, sync::Arc};
 use tokio::{
     sync::mpsc::{self, Sender},
     time,
 };
 
-use crate::websockets::{channels::game::MovePiece, handler::WsMessage};
+use crate::{
+    config::PocketsConfig,
+    websockets::{channels::game::MovePiece, handler::WsMessage},
+};
 
 use super::game::{GameDraw, GameEnd, GameMessage, PlacePiece, RedirectToPlacement};
 
@@ -119,7 +122,7 @@ pub async fn ai_channel<
             {
                 ai.redirect_to_placement(&message).await;
             } else if let Ok(state) = serde_json::from_str::<GameEnd>(&message) {
-                if state.status > 0 {
+                if state.status.is_finished() {
                     break;
                 }
             } else if let Ok(mv) = serde_json::from_str::<PlacePiece>(&message) {
@@ -315,6 +318,7 @@ where
                             .send(GameMessage::GameMove {
                                 player: String::from("AI"),
                                 game_move: mv.to_fen(),
+                                socket: None,
                             })
                             .await;
                         self.last_move = mv.to_fen();
@@ -369,6 +373,7 @@ where
             let message = GameMessage::GameMove {
                 player: "AI".to_string(),
                 game_move: format!("{}@{}", piece.to_string(), sq.to_string()),
+                socket: None,
             };
             let _ = self.game_channel.send(message).await;
         }
@@ -407,6 +412,7 @@ where
                 let message = GameMessage::GameMove {
                     player: "AI".to_string(),
                     game_move: format!("{}@{}", piece.to_string(), sq.to_string()),
+                    socket: None,
                 };
                 let _ = self.game_channel.send(message).await;
             } else {
@@ -448,6 +454,7 @@ where
                 .send(GameMessage::GameMove {
                     player: String::from("AI"),
                     game_move: format!("+{}", piece),
+                    socket: None,
                 })
                 .await;
         }
@@ -456,6 +463,7 @@ where
             .send(GameMessage::GameMove {
                 player: String::from("AI"),
                 game_move: "c".to_string(),
+                socket: None,
             })
             .await;
     }
@@ -471,19 +479,13 @@ pub struct Pockets {
 }
 
 impl Pockets {
-    pub fn new() -> Self {
-        let mini = env::var("MINI").unwrap_or("qnn".to_string());
-        let mini = Self::add_items(mini);
-        let mini_fairy = env::var("MINI_FAIRY").unwrap_or("qnn".to_string());
-        let mini_fairy = Self::add_items(mini_fairy);
-        let standard = env::var("STANDARD").unwrap_or("qnn".to_string());
-        let standard = Self::add_items(standard);
-        let standard_fairy = env::var("STANDARD_FAIRY").unwrap_or("qnn".to_string());
-        let standard_fairy = Self::add_items(standard_fairy);
-        let large = env::var("LARGE").unwrap_or("qnnr".to_string());
-        let large = Self::add_items(large);
-        let large_fairy = env::var("LARGE_FAIRY").unwrap_or("qnnrp".to_string());
-        let large_fairy = Self::add_items(large_fairy);
+    pub fn new(config: &PocketsConfig) -> Self {
+        let mini = Self::add_items(&config.mini);
+        let mini_fairy = Self::add_items(&config.mini_fairy);
+        let standard = Self::add_items(&config.standard);
+        let standard_fairy = Self::add_items(&config.standard_fairy);
+        let large = Self::add_items(&config.large);
+        let large_fairy = Self::add_items(&config.large_fairy);
 
         Self {
             mini,
@@ -495,7 +497,7 @@ impl Pockets {
         }
     }
 
-    fn add_items(pockets: String) -> Vec<String> {
+    fn add_items(pockets: &str) -> Vec<String> {
         let pockets = pockets.split(",").collect::<Vec<&str>>();
         let mut v = vec![];
         for pocket in pockets {
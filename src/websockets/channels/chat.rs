@@ -1,15 +1,31 @@
 use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize, Serializer};
+use typeshare::typeshare;
+
+use super::message_types::MessageType;
+
+/// Longer messages are dropped.
+pub const MAX_CHAT_LENGTH: usize = 140;
 
 #[derive(Clone, Serialize, Deserialize)]
+#[typeshare]
 pub struct ChatMessage {
     pub user: String,
     #[serde(serialize_with = "to_rfc3339")]
+    #[typeshare(serialized_as = "String")]
     pub time: DateTime<Local>,
     pub message: String,
 }
 
 impl ChatMessage {
+    pub fn new(user: &str, message: &str) -> Self {
+        Self {
+            user: String::from(user),
+            time: Local::now(),
+            message: String::from(message),
+        }
+    }
+
     pub fn update(&mut self, user: &String) {
         self.user = user.to_string();
 
@@ -18,6 +34,13 @@ impl ChatMessage {
     }
 }
 
+#[derive(Serialize)]
+#[typeshare]
+pub struct GameChat {
+    pub t: MessageType,
+    pub message: ChatMessage,
+}
+
 fn to_rfc3339<S>(time: &DateTime<Local>, s: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -1,27 +1,28 @@
-use std::sync::Arc;
-
-use tokio::{
-    sync::{
-        mpsc::{self, Sender},
-        Mutex,
-    },
-    time,
+use std::sync::{
+    atomic::{AtomicI64, Ordering},
+    Arc,
 };
 
+use chrono::Duration;
+use tokio::sync::mpsc::{self, Sender};
+
+use crate::database::clock::time_source::Clock;
+
 use super::game::GameMessage;
 
-pub async fn clock_task(game: Sender<GameMessage>) -> mpsc::Sender<ClockMessage> {
+pub async fn clock_task(
+    game: Sender<GameMessage>,
+    clock: Clock,
+) -> mpsc::Sender<ClockMessage> {
     let (sender, mut recv) = mpsc::channel::<ClockMessage>(20);
 
-    let interval =
-        Arc::new(Mutex::new(time::interval(time::Duration::from_secs(5))));
+    let interval = Arc::new(AtomicI64::new(5000));
     let interval_loop = interval.clone();
     tokio::spawn(async move {
         while let Some(msg) = recv.recv().await {
             match msg {
                 ClockMessage::IncreaseInterval(duration) => {
-                    let duration = time::Duration::from_millis(duration);
-                    *interval.lock().await = time::interval(duration);
+                    interval.store(duration as i64, Ordering::Relaxed);
                 }
                 ClockMessage::StopClock => {
                     break;
@@ -32,7 +33,8 @@ pub async fn clock_task(game: Sender<GameMessage>) -> mpsc::Sender<ClockMessage>
 
     tokio::spawn(async move {
         loop {
-            interval_loop.lock().await.tick().await;
+            let duration = interval_loop.load(Ordering::Relaxed);
+            clock.sleep(Duration::milliseconds(duration)).await;
             if let Err(_) = game.send(GameMessage::CheckClock).await {
                 break;
             }
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use tokio::sync::mpsc::Sender;
+use typeshare::typeshare;
+
+use crate::websockets::handler::WsMessage;
+
+use super::message_types::MessageType;
+
+/// Reason why server rejected client message.
+#[derive(Serialize_repr, Deserialize_repr, Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+#[typeshare]
+pub enum ErrorCode {
+    NotYourTurn,
+    IllegalMove,
+    WrongStage,
+    LobbyFull,
+    AlreadyPlaying,
+    OpponentPlaying,
+    GameNotStarted,
+    GameNotFound,
+    NotInGame,
+    NotPlayer,
+    OutOfTime,
+    WrongRoom,
+    InvalidMessage,
+    InvalidRequest,
+    Banned,
+    Muted,
+    Forbidden,
+    InvalidTimeControl,
+    InvalidAiLevel,
+    UnknownPlayer,
+    InvalidSubVariant,
+    ChallengeNotFound,
+    Blocked,
+}
+
+/// Sent only to socket that sent rejected message.
+#[derive(Serialize, Deserialize, Debug)]
+#[typeshare]
+pub struct WsError {
+    t: MessageType,
+    pub code: ErrorCode,
+    pub message_type: Option<MessageType>,
+    /// Rejected move, so client can take it back.
+    pub game_move: Option<String>,
+}
+
+impl WsError {
+    pub fn new(code: ErrorCode, message_type: Option<MessageType>) -> Self {
+        Self {
+            t: MessageType::Error,
+            code,
+            message_type,
+            game_move: None,
+        }
+    }
+
+    pub fn with_move(mut self, game_move: &str) -> Self {
+        self.game_move = Some(String::from(game_move));
+        self
+    }
+
+    pub async fn send(self, socket: &Sender<WsMessage>) {
+        let message = serde_json::json!(self).to_string();
+        let _ = socket.send(WsMessage::Message(message)).await;
+    }
+}
+
+/// Send error for move to player socket, if there is one.
+pub async fn reject_move(
+    socket: &Option<Sender<WsMessage>>,
+    code: ErrorCode,
+    message_type: MessageType,
+    game_move: &str,
+) {
+    if let Some(socket) = socket {
+        WsError::new(code, Some(message_type))
+            .with_move(game_move)
+            .send(socket)
+            .await;
+    }
+}
@@ -1,7 +1,6 @@
 use std::{hash::Hash, sync::Arc};
 
-use bson::DateTime;
-use chrono::{DateTime as DateTime2, Utc};
+use chrono::DateTime as DateTime2;
 use chrono::{FixedOffset, TimeDelta};
 use serde::{Deserialize, Serialize};
 use shuuro::PieceType;
@@ -20,13 +19,22 @@ use typeshare::typeshare;
 use crate::{
     database::{
         Database,
-        clock::queries::{add_game_to_db, remove_game, update_entire_game},
-        model::ShuuroGame,
+        clock::{
+            queries::{
+                add_game_to_db, blocked_by, is_blocked, remove_game,
+                update_entire_game,
+            },
+            time_source::TimeSource,
+        },
+        model::{GameResult, GameStatus, ShuuroGame},
+        replay::outcome_status,
     },
     websockets::handler::WsMessage,
 };
 
 use super::ai::ai_channel;
+use super::chat::{ChatMessage, GameChat};
+use super::errors::{ErrorCode, WsError, reject_move};
 use super::game_requests::GameRequestMessage;
 use super::tv::TvMessage;
 use super::{
@@ -54,7 +62,7 @@ pub async fn game_task<
     ws: Arc<WsState>,
     game_request: GameRequest,
     id: String,
-    caller: String,
+    mut caller: String,
     unfinished: Option<ShuuroGame>,
 ) where
     S: Square + Hash + Send + 'static + std::marker::Sync,
@@ -106,6 +114,7 @@ pub async fn game_task<
         None => ShuuroGame::from((&game_request, &colors, id.as_str())),
     };
     let mut game = add_game_to_db(&db.mongo.games, game, started).await;
+    game.tc.clock = db.clock.clone();
     let (mut selection, mut placement, mut fight) =
         (Selection::<S>::default(), P::new(), P::new());
 
@@ -196,7 +205,7 @@ pub async fn game_task<
         .await;
     }
 
-    let clock_task = clock_task(send.clone()).await;
+    let clock_task = clock_task(send.clone(), db.clock.clone()).await;
     let mut current_interval = 15_000;
     tokio::spawn(async move {
         while let Some(message) = recv.recv().await {
@@ -205,8 +214,16 @@ pub async fn game_task<
                     if watchers.players.len() == 10 {
                         continue;
                     }
-                    watchers.add_watcher(player.clone(), sender);
+                    watchers.add_watcher(player.clone(), sender.clone());
                     if started == false && &player != &caller {
+                        // Open seek can't be joined by blocked player.
+                        if other_player == ""
+                            && is_blocked(&db.mongo.players, &caller, &player).await
+                        {
+                            let t = Some(MessageType::ChangeRoom);
+                            WsError::new(ErrorCode::Blocked, t).send(&sender).await;
+                            continue;
+                        }
                         if other_player == "" || &other_player == &player {
                             other_player = player.clone();
                             let index = game
@@ -223,7 +240,7 @@ pub async fn game_task<
                                 serde_json::json!(StartClock {
                                     t: MessageType::StartClock,
                                     players: game.players.clone(),
-                                    click: Utc::now().into()
+                                    click: db.clock.now()
                                 })
                                 .to_string(),
                             );
@@ -234,6 +251,13 @@ pub async fn game_task<
                                     other_player.to_string(),
                                 ))
                                 .await;
+                            let _ = ws
+                                .players
+                                .send(PlayersMessage::GameStarted {
+                                    game: id.to_string(),
+                                    players: game.players.clone(),
+                                })
+                                .await;
                         }
                     }
                 }
@@ -275,16 +299,40 @@ pub async fn game_task<
                 GameMessage::GameMove {
                     ref player,
                     game_move,
+                    socket,
                 } => {
+                    let parsed = Move::<S>::from_sfen(&game_move);
+                    let message_type = move_type(&parsed);
                     if !started {
+                        reject_move(
+                            &socket,
+                            ErrorCode::GameNotStarted,
+                            message_type,
+                            &game_move,
+                        )
+                        .await;
                         continue;
                     }
                     let Some(index) = player_index(&game.players, &player) else {
+                        reject_move(
+                            &socket,
+                            ErrorCode::NotPlayer,
+                            message_type,
+                            &game_move,
+                        )
+                        .await;
                         continue;
                     };
 
-                    let Some(m) = Move::<S>::from_sfen(&game_move) else {
+                    let Some(m) = parsed else {
                         if game.current_stage != 0 {
+                            reject_move(
+                                &socket,
+                                ErrorCode::IllegalMove,
+                                message_type,
+                                &game_move,
+                            )
+                            .await;
                             continue;
                         }
                         let me = Color::from(index);
@@ -303,13 +351,34 @@ pub async fn game_task<
                     };
                     if let Move::Select { piece } = m {
                         if game.current_stage != 0 {
+                            reject_move(
+                                &socket,
+                                ErrorCode::WrongStage,
+                                message_type,
+                                &game_move,
+                            )
+                            .await;
                             continue;
                         }
                         let me = Color::from(index);
                         if piece.color != me {
+                            reject_move(
+                                &socket,
+                                ErrorCode::IllegalMove,
+                                message_type,
+                                &game_move,
+                            )
+                            .await;
                             continue;
                         }
                         let Some(_) = selection.play(m) else {
+                            reject_move(
+                                &socket,
+                                ErrorCode::IllegalMove,
+                                message_type,
+                                &game_move,
+                            )
+                            .await;
                             continue;
                         };
 
@@ -327,25 +396,63 @@ pub async fn game_task<
                         .await;
                     } else if let Move::Put { to, piece } = m {
                         if game.current_stage != 1 {
+                            reject_move(
+                                &socket,
+                                ErrorCode::WrongStage,
+                                message_type,
+                                &game_move,
+                            )
+                            .await;
                             continue;
                         }
                         let color = Color::from(index);
                         if placement.side_to_move() != color {
+                            reject_move(
+                                &socket,
+                                ErrorCode::NotYourTurn,
+                                message_type,
+                                &game_move,
+                            )
+                            .await;
                             continue;
                         }
 
-                        let Some(clocks) = game.tc.play(index) else {
+                        // Clock is changed only after move is accepted.
+                        let mut tc = game.tc.clone();
+                        let Some((clocks, move_time)) = tc.timed_play(index) else {
+                            reject_move(
+                                &socket,
+                                ErrorCode::OutOfTime,
+                                message_type,
+                                &game_move,
+                            )
+                            .await;
                             continue;
                         };
-                        game.clocks = game.tc.clocks;
-                        game.last_clock = DateTime::now();
 
                         if color != piece.color {
+                            reject_move(
+                                &socket,
+                                ErrorCode::IllegalMove,
+                                message_type,
+                                &game_move,
+                            )
+                            .await;
                             continue;
                         }
                         let Some(sfen) = placement.place(piece, to) else {
+                            reject_move(
+                                &socket,
+                                ErrorCode::IllegalMove,
+                                message_type,
+                                &game_move,
+                            )
+                            .await;
                             continue;
                         };
+                        game.tc = tc;
+                        game.clocks = game.tc.clocks;
+                        game.last_clock = db.clock.bson_now();
                         game.draws = [false, false];
                         let mut first_move_error = false;
                         let next_stage = {
@@ -362,7 +469,7 @@ pub async fn game_task<
                             first_move_error = {
                                 game.current_stage = 2;
                                 game.tc.update_stage(2);
-                                game.last_clock = DateTime::now();
+                                game.last_clock = db.clock.bson_now();
                                 let sfen = placement.generate_sfen();
                                 let outcome = fight.set_sfen(&sfen);
                                 if let Ok(outcome) = outcome {
@@ -380,6 +487,7 @@ pub async fn game_task<
                             placement.get_hand(Color::Black, false),
                         ];
                         game.history.1.push(m.to_fen());
+                        game.move_times.0.push(move_time);
                         let message = PlacePiece {
                             clocks,
                             first_move_error,
@@ -403,7 +511,7 @@ pub async fn game_task<
                             update_entire_game(&db.mongo.games, &game).await;
                             close_game(
                                 clock_task,
-                                fight.side_to_move().flip().index() as u8,
+                                GameResult::from(fight.side_to_move().flip()),
                                 game.status,
                                 &watchers,
                                 ws.game_requests.clone(),
@@ -426,37 +534,83 @@ pub async fn game_task<
                     } = m
                     {
                         if game.current_stage != 2 {
+                            reject_move(
+                                &socket,
+                                ErrorCode::WrongStage,
+                                message_type,
+                                &game_move,
+                            )
+                            .await;
                             continue;
                         }
                         let color = Color::from(index);
                         if fight.side_to_move() != color {
+                            reject_move(
+                                &socket,
+                                ErrorCode::NotYourTurn,
+                                message_type,
+                                &game_move,
+                            )
+                            .await;
                             continue;
                         }
 
-                        let Some(clocks) = game.tc.play(index) else {
+                        // Clock is changed only after move is accepted.
+                        let mut tc = game.tc.clone();
+                        let Some((clocks, move_time)) = tc.timed_play(index) else {
+                            reject_move(
+                                &socket,
+                                ErrorCode::OutOfTime,
+                                message_type,
+                                &game_move,
+                            )
+                            .await;
                             continue;
                         };
-                        game.clocks = game.tc.clocks;
-                        game.last_clock = DateTime::now();
                         let Some(piece) = fight.piece_at(from) else {
+                            reject_move(
+                                &socket,
+                                ErrorCode::IllegalMove,
+                                message_type,
+                                &game_move,
+                            )
+                            .await;
                             continue;
                         };
                         if color != piece.color {
+                            reject_move(
+                                &socket,
+                                ErrorCode::IllegalMove,
+                                message_type,
+                                &game_move,
+                            )
+                            .await;
                             continue;
                         }
 
                         let Ok(outcome) = fight.play(&game_move) else {
+                            reject_move(
+                                &socket,
+                                ErrorCode::IllegalMove,
+                                message_type,
+                                &game_move,
+                            )
+                            .await;
                             continue;
                         };
+                        game.tc = tc;
+                        game.clocks = game.tc.clocks;
+                        game.last_clock = db.clock.bson_now();
                         update_status(&mut game, outcome);
 
                         game.side_to_move = fight.side_to_move() as u8;
                         game.sfen = fight.get_sfen_history().first().2;
                         game.history.2.push(game_move.to_string());
+                        game.move_times.1.push(move_time);
                         let message = MovePiece {
                             clocks,
                             status: game.status,
-                            result: 2,
+                            result: game.result,
                             game_move,
                             t: MessageType::MovePiece,
                         };
@@ -472,7 +626,7 @@ pub async fn game_task<
                                 first_move_error: false,
                             })
                             .await;
-                        if game.status > 0 {
+                        if game.status.is_finished() {
                             update_entire_game(&db.mongo.games, &game).await;
                             close_game(
                                 clock_task,
@@ -504,12 +658,12 @@ pub async fn game_task<
                     };
                     game.draws[index] = true;
                     if game.draws == [true, true] {
-                        game.status = 5;
+                        game.set_status(GameStatus::Agreement, GameResult::Draw);
                         update_entire_game(&db.mongo.games, &game).await;
                         close_game(
                             clock_task,
-                            2,
-                            5,
+                            game.result,
+                            game.status,
                             &watchers,
                             ws.game_requests.clone(),
                             &game,
@@ -547,15 +701,15 @@ pub async fn game_task<
                     let Some(index) = player_index(&game.players, &player) else {
                         continue;
                     };
-                    game.status = 7;
-                    game.result = index as u8;
+                    let result = GameResult::from(Color::from(index));
+                    game.set_status(GameStatus::Resign, result);
                     game.tc.play(index);
-                    game.last_clock = DateTime::now();
+                    game.last_clock = db.clock.bson_now();
                     update_entire_game(&db.mongo.games, &game).await;
                     close_game(
                         clock_task,
                         game.result,
-                        7,
+                        game.status,
                         &watchers,
                         ws.game_requests.clone(),
                         &game,
@@ -571,13 +725,41 @@ pub async fn game_task<
                         .await;
                     break;
                 }
+                GameMessage::Chat { message, shadow } => {
+                    let list = if shadow {
+                        vec![message.user.to_string()]
+                    } else {
+                        // Hidden from watchers that blocked the author.
+                        let watching = watchers.players.keys().cloned().collect();
+                        let blockers =
+                            blocked_by(&db.mongo.players, &message.user, watching)
+                                .await;
+                        watchers
+                            .players
+                            .keys()
+                            .filter(|player| !blockers.contains(player))
+                            .cloned()
+                            .collect()
+                    };
+                    let send_to = SendTo::Players {
+                        list,
+                        to_others: false,
+                    };
+                    let message = GameChat {
+                        t: MessageType::ChatMessage,
+                        message,
+                    };
+                    let message =
+                        WsMessage::Message(serde_json::json!(message).to_string());
+                    watchers.notify(message, send_to).await;
+                }
                 GameMessage::Abort => {
                     let _id =
                         remove_game(&db.mongo.games, game._id.to_string()).await;
                     close_game(
                         clock_task,
-                        2,
-                        10,
+                        GameResult::Draw,
+                        GameStatus::Aborted,
                         &watchers,
                         ws.game_requests.clone(),
                         &game,
@@ -593,6 +775,28 @@ pub async fn game_task<
                         .await;
                     break;
                 }
+                GameMessage::End { status, result } => {
+                    game.set_status(status, result);
+                    game.last_clock = db.clock.bson_now();
+                    update_entire_game(&db.mongo.games, &game).await;
+                    close_game(
+                        clock_task,
+                        game.result,
+                        game.status,
+                        &watchers,
+                        ws.game_requests.clone(),
+                        &game,
+                        ws.games.clone(),
+                    )
+                    .await;
+                    let _ = ws
+                        .tv
+                        .send(TvMessage::Remove {
+                            id: game._id.to_string(),
+                        })
+                        .await;
+                    break;
+                }
                 GameMessage::CheckClock => {
                     let mut stm = game.side_to_move;
                     if !started {
@@ -610,15 +814,17 @@ pub async fn game_task<
                         if !confirmed.contains(&true) {
                             let clock = game.tc.current_duration(1);
                             if clock.is_none() {
-                                game.result = 2;
-                                game.status = 8;
+                                game.set_status(
+                                    GameStatus::Timeout,
+                                    GameResult::Draw,
+                                );
                                 game.tc.set_to_zero(Color::White);
                                 game.tc.set_to_zero(Color::Black);
                                 update_entire_game(&db.mongo.games, &game).await;
                                 close_game(
                                     clock_task,
-                                    2,
-                                    8,
+                                    game.result,
+                                    game.status,
                                     &watchers,
                                     ws.game_requests.clone(),
                                     &game,
@@ -648,14 +854,14 @@ pub async fn game_task<
                         };
                     }
                     let Some(clock) = game.tc.current_duration(stm.into()) else {
-                        game.result = stm as u8;
-                        game.status = 8;
+                        let result = GameResult::from(Color::from(stm as usize));
+                        game.set_status(GameStatus::Timeout, result);
                         game.tc.set_to_zero(Color::from(stm as usize));
                         update_entire_game(&db.mongo.games, &game).await;
                         close_game(
                             clock_task,
-                            stm as u8,
-                            8,
+                            game.result,
+                            game.status,
                             &watchers,
                             ws.game_requests.clone(),
                             &game,
@@ -686,14 +892,39 @@ pub async fn game_task<
 
                     continue;
                 }
+                GameMessage::RenamePlayer { from, to } => {
+                    // Both names can't play in same game.
+                    if player_index(&game.players, &to).is_some() {
+                        continue;
+                    }
+                    let Some(index) = player_index(&game.players, &from) else {
+                        continue;
+                    };
+                    game.players[index] = to.clone();
+                    if caller == from {
+                        caller = to.clone();
+                    }
+                    if other_player == from {
+                        other_player = to.clone();
+                    }
+                    watchers.rename_watcher(&from, &to);
+                    // Merge can run before this update, name must be kept.
+                    update_entire_game(&db.mongo.games, &game).await;
+                    let message = PlayersRenamed {
+                        t: MessageType::RenamePlayer,
+                        players: game.players.clone(),
+                    };
+                    let message =
+                        WsMessage::Message(serde_json::json!(message).to_string());
+                    watchers.notify(message, SendTo::Everyone).await;
+                }
                 GameMessage::SaveState => {
-                    game.result = 2;
-                    game.status = -2;
+                    game.set_status(GameStatus::NotStarted, GameResult::Draw);
                     update_entire_game(&db.mongo.games, &game).await;
                     close_game(
                         clock_task,
-                        2,
-                        -2,
+                        game.result,
+                        game.status,
                         &watchers,
                         ws.game_requests.clone(),
                         &game,
@@ -708,49 +939,8 @@ pub async fn game_task<
 }
 
 fn update_status(game: &mut ShuuroGame, outcome: &Outcome) {
-    match outcome {
-        Outcome::Check { color: _ } => {
-            game.status = -1;
-        }
-        Outcome::Stalemate => {
-            game.status = 3;
-            game.result = 2;
-        }
-        Outcome::DrawByAgreement => {
-            game.status = 5;
-            game.result = 2;
-        }
-        Outcome::DrawByRepetition => {
-            game.status = 4;
-            game.result = 2;
-        }
-        Outcome::DrawByMaterial => {
-            game.status = 6;
-            game.result = 2;
-        }
-        Outcome::Checkmate { color } => {
-            game.status = 1;
-            game.result = *color as u8;
-        }
-        Outcome::MoveOk => {
-            game.status = -1;
-        }
-        Outcome::MoveNotOk => {
-            game.status = -2;
-        }
-        Outcome::Resign { color } => {
-            game.status = 7;
-            game.result = *color as u8;
-        }
-        Outcome::LostOnTime { color } => {
-            game.status = 8;
-            game.result = *color as u8;
-        }
-        Outcome::FirstMoveError { color } => {
-            game.status = 9;
-            game.result = *color as u8;
-        }
-    }
+    let (status, result) = outcome_status(outcome, game.result);
+    game.set_status(status, result);
 }
 
 pub enum GameMessage {
@@ -758,12 +948,21 @@ pub enum GameMessage {
     Leave(String),
     GetGame(tokio::sync::oneshot::Sender<ShuuroGame>),
     GetHand(String),
-    GameMove { player: String, game_move: String },
+    GameMove {
+        player: String,
+        game_move: String,
+        /// Socket that gets error if move is rejected.
+        socket: Option<Sender<WsMessage>>,
+    },
     Draw(String),
     Resign(String),
+    Chat { message: ChatMessage, shadow: bool },
     Abort,
+    /// Admin ended game.
+    End { status: GameStatus, result: GameResult },
     CheckClock,
     SaveState,
+    RenamePlayer { from: String, to: String },
 }
 
 pub enum MoveType {
@@ -776,8 +975,9 @@ pub enum MoveType {
 #[derive(Serialize, Deserialize)]
 pub struct GameEnd {
     t: MessageType,
-    result: u8,
-    pub status: i32,
+    result: GameResult,
+    pub status: GameStatus,
+    termination: String,
 }
 
 #[typeshare]
@@ -831,8 +1031,8 @@ pub struct MovePiece {
     t: MessageType,
     #[typeshare(serialized_as = "[u8; 2]")]
     clocks: [u64; 2],
-    status: i32,
-    result: u8,
+    status: GameStatus,
+    result: GameResult,
     pub game_move: String,
 }
 
@@ -845,6 +1045,14 @@ pub struct StartClock {
     click: DateTime2<FixedOffset>,
 }
 
+/// Player name changed after guest logged in.
+#[typeshare]
+#[derive(Serialize, Debug, Clone, Deserialize)]
+pub struct PlayersRenamed {
+    t: MessageType,
+    players: [String; 2],
+}
+
 fn clocks(clocks: [TimeDelta; 2]) -> u64 {
     let clock;
     if clocks[1] < clocks[0] {
@@ -864,8 +1072,8 @@ fn clocks(clocks: [TimeDelta; 2]) -> u64 {
 
 async fn close_game(
     clock_task: mpsc::Sender<ClockMessage>,
-    result: u8,
-    status: i32,
+    result: GameResult,
+    status: GameStatus,
     watchers: &Watchers,
     requests: mpsc::Sender<GameRequestMessage>,
     game: &ShuuroGame,
@@ -877,6 +1085,7 @@ async fn close_game(
         t: MessageType::GameEnd,
         result,
         status,
+        termination: String::from(status.termination()),
     };
     watchers
         .notify(
@@ -894,6 +1103,16 @@ async fn close_game(
         .await;
 }
 
+/// Message type that client used for this move.
+fn move_type<S: Square>(m: &Option<Move<S>>) -> MessageType {
+    match m {
+        None => MessageType::ConfirmSelection,
+        Some(Move::Select { .. }) => MessageType::SelectMove,
+        Some(Move::Put { .. }) => MessageType::PlacePiece,
+        Some(_) => MessageType::MovePiece,
+    }
+}
+
 pub fn player_index(p: &[String; 2], u: &String) -> Option<usize> {
     p.iter().position(|x| x == u)
 }
@@ -937,7 +1156,7 @@ pub async fn confirm_selection<S, B, A, P>(
         }
         game.current_stage = 1;
         game.tc.update_stage(1);
-        game.last_clock = DateTime::now();
+        game.last_clock = game.tc.clock.bson_now();
         {
             let w = selection.to_sfen(Color::White, false);
             let b = selection.to_sfen(Color::Black, false);
@@ -954,7 +1173,7 @@ pub async fn confirm_selection<S, B, A, P>(
         let redirect = RedirectToPlacement {
             t: MessageType::RedirectToGame,
             id: game._id.to_string(),
-            last_clock: Utc::now().into(),
+            last_clock: game.tc.clock.now(),
             players: game.players.clone(),
             sfen: game.sfen.to_string(),
             variant: game.variant as u8,
@@ -1,4 +1,5 @@
-use crate::database::serde_helpers::deserialize_color;
+use crate::database::serde_helpers::{deserialize_color, serialize_color};
+use chrono::TimeDelta;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use shuuro::{
@@ -15,23 +16,36 @@ use shuuro_engine::{
     engine6::search::{Defs6, Engine6},
     engine8::search::{Defs8, Engine8},
 };
-use std::{collections::HashSet, sync::Arc};
+use std::{collections::HashSet, ops::RangeInclusive, sync::Arc};
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::Sender;
 use typeshare::typeshare;
 
 use crate::{
     database::{
-        clock::queries::game_id,
-        serde_helpers::{deserialize_subvariant, deserialize_variant},
+        clock::{
+            queries::{
+                add_challenge, close_challenge, game_id, get_challenge, get_player,
+                is_blocked, is_sanctioned, random_game_id,
+            },
+            time_source::Clock,
+        },
+        model::{Challenge, ChallengeStatus, NotificationKind, SanctionKind},
+        notifications::notify_player,
+        serde_helpers::{
+            deserialize_subvariant, deserialize_variant, serialize_subvariant,
+            serialize_variant,
+        },
         Database,
     },
     websockets::handler::WsMessage,
 };
 
 use super::{
+    errors::{ErrorCode, WsError},
     game::game_task,
     message_types::MessageType,
+    players::PlayersMessage,
     watchers::{SendTo, Watchers},
     WsState,
 };
@@ -48,11 +62,15 @@ pub const DURATION_RANGE: [i64; 28] = [
     1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 25, 30,
     35, 40, 45, 60, 75, 90,
 ];
+pub const AI_LEVELS: RangeInclusive<u8> = 1..=4;
+/// How long challenged player has to answer.
+pub const CHALLENGE_TIMEOUT: TimeDelta = TimeDelta::seconds(120);
 
 pub async fn game_requests_task(
     db: Arc<Database>,
 ) -> mpsc::Sender<GameRequestMessage> {
     let (sender, mut recv) = mpsc::channel(64);
+    let task = sender.clone();
     let _ = tokio::spawn(async move {
         let mut watchers = Watchers::new();
         let mut playing = HashSet::new();
@@ -61,93 +79,201 @@ pub async fn game_requests_task(
         let mut ai_games_count = 0;
         while let Some(message) = recv.recv().await {
             match message {
-                GameRequestMessage::AddGameRequest { caller, request } => {
+                GameRequestMessage::AddGameRequest {
+                    caller,
+                    request,
+                    socket,
+                } => {
+                    let t = Some(MessageType::AddGameRequest);
                     if playing.contains(&caller) {
+                        WsError::new(ErrorCode::AlreadyPlaying, t)
+                            .send(&socket)
+                            .await;
+                        continue;
+                    }
+                    let now = db.clock.bson_now();
+                    let ban = SanctionKind::Ban;
+                    if is_sanctioned(&db.mongo.sanctions, &caller, ban, now).await {
+                        WsError::new(ErrorCode::Banned, t).send(&socket).await;
                         continue;
                     }
                     if playing.len() >= 60 {
+                        WsError::new(ErrorCode::LobbyFull, t).send(&socket).await;
+                        continue;
+                    }
+                    if let Err(code) = request.check() {
+                        WsError::new(code, t).send(&socket).await;
                         continue;
                     }
                     let friend = request.game_type.player_name();
 
                     if &friend == &caller {
+                        WsError::new(ErrorCode::InvalidRequest, t)
+                            .send(&socket)
+                            .await;
+                        continue;
+                    } else if is_friend(&request.game_type)
+                        && get_player(&db.mongo.players, &friend).await.is_none()
+                    {
+                        WsError::new(ErrorCode::UnknownPlayer, t)
+                            .send(&socket)
+                            .await;
+                        continue;
+                    } else if is_friend(&request.game_type)
+                        && is_blocked(&db.mongo.players, &friend, &caller).await
+                    {
+                        WsError::new(ErrorCode::Blocked, t).send(&socket).await;
                         continue;
                     } else if playing.contains(&friend) {
+                        WsError::new(ErrorCode::OpponentPlaying, t)
+                            .send(&socket)
+                            .await;
                         continue;
                     }
                     if &friend == "AI" {
                         if ai_games_count == 10 {
+                            WsError::new(ErrorCode::LobbyFull, t)
+                                .send(&socket)
+                                .await;
                             continue;
                         }
                         ai_games_count += 1;
                     }
 
+                    if is_friend(&request.game_type) {
+                        let challenge = Challenge::new(
+                            &random_game_id(),
+                            &caller,
+                            &friend,
+                            request,
+                            db.clock.bson_now(),
+                            CHALLENGE_TIMEOUT,
+                        );
+                        add_challenge(&db.mongo.challenges, &challenge).await;
+                        let id = challenge._id.clone();
+                        expire_challenge(task.clone(), db.clock.clone(), id.clone());
+                        let _ = ws
+                            .players
+                            .send(PlayersMessage::Challenge(challenge))
+                            .await;
+                        let kind = NotificationKind::Challenge {
+                            id,
+                            challenger: caller,
+                        };
+                        notify_player(&db, &ws.players, &friend, kind).await;
+                        continue;
+                    }
+
                     playing.insert(caller.to_string());
-                    match request.variant {
-                        Variant::Shuuro | Variant::ShuuroFairy => {
-                            game_task::<
-                                Square12,
-                                BB12<Square12>,
-                                Attacks12<Square12, BB12<Square12>>,
-                                P12<Square12, BB12<Square12>>,
-                                Engine12,
-                                Defs12,
-                                12,
-                                144,
-                                11,
-                            >(
-                                db.clone(),
-                                ws.clone(),
-                                request,
-                                game_id(&db.mongo.games).await,
-                                caller,
-                                None,
-                            )
+                    let id = game_id(&db.mongo.games).await;
+                    create_game(&db, &ws, request, caller, id).await;
+                }
+                GameRequestMessage::AcceptChallenge { id, player, socket } => {
+                    let t = Some(MessageType::AcceptChallenge);
+                    let Some(challenge) =
+                        get_challenge(&db.mongo.challenges, &id).await
+                    else {
+                        WsError::new(ErrorCode::ChallengeNotFound, t)
+                            .send(&socket)
                             .await;
-                        }
-                        Variant::ShuuroMini | Variant::ShuuroMiniFairy => {
-                            game_task::<
-                                Square6,
-                                BB6<Square6>,
-                                Attacks6<Square6, BB6<Square6>>,
-                                P6<Square6, BB6<Square6>>,
-                                Engine6,
-                                Defs6,
-                                6,
-                                36,
-                                4,
-                            >(
-                                db.clone(),
-                                ws.clone(),
-                                request,
-                                game_id(&db.mongo.games).await,
-                                caller,
-                                None,
-                            )
+                        continue;
+                    };
+                    if challenge.target != player
+                        || challenge.status != ChallengeStatus::Pending
+                        || challenge.expires_at <= db.clock.bson_now()
+                    {
+                        WsError::new(ErrorCode::ChallengeNotFound, t)
+                            .send(&socket)
                             .await;
-                        }
-                        Variant::Standard | Variant::StandardFairy => {
-                            game_task::<
-                                Square8,
-                                BB8<Square8>,
-                                Attacks8<Square8, BB8<Square8>>,
-                                P8<Square8, BB8<Square8>>,
-                                Engine8,
-                                Defs8,
-                                8,
-                                64,
-                                7,
-                            >(
-                                db.clone(),
-                                ws.clone(),
-                                request,
-                                game_id(&db.mongo.games).await,
-                                caller,
-                                None,
-                            )
+                        continue;
+                    }
+                    if playing.contains(&player) {
+                        WsError::new(ErrorCode::AlreadyPlaying, t)
+                            .send(&socket)
                             .await;
-                        }
+                        continue;
+                    } else if playing.contains(&challenge.challenger) {
+                        WsError::new(ErrorCode::OpponentPlaying, t)
+                            .send(&socket)
+                            .await;
+                        continue;
+                    } else if playing.len() >= 60 {
+                        WsError::new(ErrorCode::LobbyFull, t).send(&socket).await;
+                        continue;
+                    }
+                    let game = game_id(&db.mongo.games).await;
+                    let Some(challenge) = close_challenge(
+                        &db.mongo.challenges,
+                        &id,
+                        ChallengeStatus::Accepted,
+                        None,
+                        Some(game.clone()),
+                    )
+                    .await
+                    else {
+                        WsError::new(ErrorCode::ChallengeNotFound, t)
+                            .send(&socket)
+                            .await;
+                        continue;
+                    };
+                    let challenger = challenge.challenger.clone();
+                    playing.insert(challenger.clone());
+                    let request = challenge.request.clone();
+                    let _ =
+                        ws.players.send(PlayersMessage::Challenge(challenge)).await;
+                    create_game(&db, &ws, request, challenger, game.clone()).await;
+                    let _ = ws
+                        .players
+                        .send(PlayersMessage::Redirect { game, player })
+                        .await;
+                }
+                GameRequestMessage::DeclineChallenge {
+                    id,
+                    player,
+                    reason,
+                    socket,
+                } => {
+                    let t = Some(MessageType::DeclineChallenge);
+                    let target = get_challenge(&db.mongo.challenges, &id)
+                        .await
+                        .map(|challenge| challenge.target);
+                    if target.as_ref() != Some(&player) {
+                        WsError::new(ErrorCode::ChallengeNotFound, t)
+                            .send(&socket)
+                            .await;
+                        continue;
+                    }
+                    let Some(challenge) = close_challenge(
+                        &db.mongo.challenges,
+                        &id,
+                        ChallengeStatus::Declined,
+                        reason,
+                        None,
+                    )
+                    .await
+                    else {
+                        WsError::new(ErrorCode::ChallengeNotFound, t)
+                            .send(&socket)
+                            .await;
+                        continue;
+                    };
+                    let _ =
+                        ws.players.send(PlayersMessage::Challenge(challenge)).await;
+                }
+                GameRequestMessage::ExpireChallenge(id) => {
+                    let Some(challenge) = close_challenge(
+                        &db.mongo.challenges,
+                        &id,
+                        ChallengeStatus::Expired,
+                        None,
+                        None,
+                    )
+                    .await
+                    else {
+                        continue;
                     };
+                    let _ =
+                        ws.players.send(PlayersMessage::Challenge(challenge)).await;
                 }
                 GameRequestMessage::RedirectToGame => {}
                 GameRequestMessage::Join(player, sender) => {
@@ -169,6 +295,10 @@ pub async fn game_requests_task(
                 }
                 GameRequestMessage::SetWs(ws_state) => ws = ws_state,
                 GameRequestMessage::RemovePlayers(players) => {
+                    let _ = ws
+                        .players
+                        .send(PlayersMessage::GameEnded(players.clone()))
+                        .await;
                     for i in players {
                         playing.remove(&i);
                         if i == "AI" {
@@ -206,12 +336,79 @@ pub async fn game_requests_task(
                         playing.insert(player);
                     }
                 }
+                GameRequestMessage::RenamePlayer { old, new } => {
+                    if playing.remove(&old) {
+                        playing.insert(new);
+                    }
+                }
             }
         }
     });
     sender
 }
 
+/// Start game task for request, `caller` is redirected to it.
+async fn create_game(
+    db: &Arc<Database>,
+    ws: &Arc<WsState>,
+    request: GameRequest,
+    caller: String,
+    id: String,
+) {
+    match request.variant {
+        Variant::Shuuro | Variant::ShuuroFairy => {
+            game_task::<
+                Square12,
+                BB12<Square12>,
+                Attacks12<Square12, BB12<Square12>>,
+                P12<Square12, BB12<Square12>>,
+                Engine12,
+                Defs12,
+                12,
+                144,
+                11,
+            >(db.clone(), ws.clone(), request, id, caller, None)
+            .await;
+        }
+        Variant::ShuuroMini | Variant::ShuuroMiniFairy => {
+            game_task::<
+                Square6,
+                BB6<Square6>,
+                Attacks6<Square6, BB6<Square6>>,
+                P6<Square6, BB6<Square6>>,
+                Engine6,
+                Defs6,
+                6,
+                36,
+                4,
+            >(db.clone(), ws.clone(), request, id, caller, None)
+            .await;
+        }
+        Variant::Standard | Variant::StandardFairy => {
+            game_task::<
+                Square8,
+                BB8<Square8>,
+                Attacks8<Square8, BB8<Square8>>,
+                P8<Square8, BB8<Square8>>,
+                Engine8,
+                Defs8,
+                8,
+                64,
+                7,
+            >(db.clone(), ws.clone(), request, id, caller, None)
+            .await;
+        }
+    };
+}
+
+/// Challenge is expired after timeout, unless it's already closed.
+fn expire_challenge(task: Sender<GameRequestMessage>, clock: Clock, id: String) {
+    tokio::spawn(async move {
+        clock.sleep(CHALLENGE_TIMEOUT).await;
+        let _ = task.send(GameRequestMessage::ExpireChallenge(id)).await;
+    });
+}
+
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
 #[serde(tag = "type", content = "content")]
 #[typeshare]
@@ -236,22 +433,64 @@ impl TypeOfGame {
     }
 }
 
+/// Empty friend name is open challenge, anyone with link can join.
+fn is_friend(game_type: &TypeOfGame) -> bool {
+    matches!(game_type, TypeOfGame::VsFriend(name) if !name.is_empty())
+}
+
+/// Sub variants are only defined for standard board.
+fn sub_variant_fits(variant: Variant, sub_variant: SubVariant) -> bool {
+    match sub_variant.index() {
+        // Standard and StandardPlacement
+        0 | 3 => variant == Variant::Standard,
+        // StandardFairy1 and StandardFairy2
+        1 | 2 => variant == Variant::StandardFairy,
+        _ => false,
+    }
+}
+
 #[derive(Clone)]
 pub enum GameRequestMessage {
     AddGameRequest {
         caller: String,
         request: GameRequest,
+        socket: Sender<WsMessage>,
+    },
+    AcceptChallenge {
+        id: String,
+        player: String,
+        socket: Sender<WsMessage>,
+    },
+    DeclineChallenge {
+        id: String,
+        player: String,
+        reason: Option<String>,
+        socket: Sender<WsMessage>,
     },
+    ExpireChallenge(String),
     Join(String, Sender<WsMessage>),
     Leave(String),
     RedirectToGame,
     SetWs(Arc<WsState>),
     RemovePlayers([String; 2]),
     AddActivePlayer(String),
+    /// Guest merged into account while playing.
+    RenamePlayer {
+        old: String,
+        new: String,
+    },
     NewGame,
 }
 
-#[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
+/// Sent by challenged player.
+#[derive(Deserialize)]
+#[typeshare]
+pub struct DeclinedChallenge {
+    pub id: String,
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
 #[typeshare]
 pub struct GameRequest {
     #[typeshare(serialized_as = "u8")]
@@ -266,6 +505,7 @@ pub struct GameRequest {
     #[serde(deserialize_with = "deserialize_subvariant")]
     #[typeshare(serialized_as = "Option<u8>")]
     pub sub_variant: Option<SubVariant>,
+    #[serde(serialize_with = "serialize_color")]
     #[serde(deserialize_with = "deserialize_color")]
     #[typeshare(serialized_as = "u8")]
     color: Color,
@@ -274,13 +514,29 @@ pub struct GameRequest {
 
 impl GameRequest {
     pub fn is_valid(&self) -> bool {
-        if VARIANTS.contains(&self.variant.to_string().as_str())
-            && DURATION_RANGE.contains(&self.minutes)
-            && (DURATION_RANGE.contains(&self.incr) || self.incr == 0)
+        self.check().is_ok()
+    }
+
+    /// Checks that don't need database, friend is checked in
+    /// `game_requests_task`. Every variant number is mapped to some variant
+    /// while deserializing.
+    pub fn check(&self) -> Result<(), ErrorCode> {
+        if !DURATION_RANGE.contains(&self.minutes)
+            || !(DURATION_RANGE.contains(&self.incr) || self.incr == 0)
         {
-            return true;
+            return Err(ErrorCode::InvalidTimeControl);
         }
-        false
+        if let TypeOfGame::VsAi(level) = self.game_type {
+            if !AI_LEVELS.contains(&level) {
+                return Err(ErrorCode::InvalidAiLevel);
+            }
+        }
+        if let Some(sub_variant) = self.sub_variant {
+            if !sub_variant_fits(self.variant, sub_variant) {
+                return Err(ErrorCode::InvalidSubVariant);
+            }
+        }
+        Ok(())
     }
 
     pub fn colors(&self, player: &String, other: &String) -> [String; 2] {
@@ -322,3 +578,50 @@ pub struct GamesCount {
     #[typeshare(serialized_as = "u8")]
     count: u64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_control_is_checked() {
+        let mut request = GameRequest::empty();
+        assert_eq!(request.check(), Ok(()));
+        request.incr = 0;
+        assert_eq!(request.check(), Ok(()));
+        request.minutes = 0;
+        assert_eq!(request.check(), Err(ErrorCode::InvalidTimeControl));
+        request.minutes = 5;
+        request.incr = -1;
+        assert_eq!(request.check(), Err(ErrorCode::InvalidTimeControl));
+        request.incr = 91;
+        assert_eq!(request.check(), Err(ErrorCode::InvalidTimeControl));
+    }
+
+    #[test]
+    fn ai_level_is_checked() {
+        let mut request = GameRequest::empty();
+        request.game_type = TypeOfGame::VsAi(0);
+        assert_eq!(request.check(), Err(ErrorCode::InvalidAiLevel));
+        request.game_type = TypeOfGame::VsAi(4);
+        assert_eq!(request.check(), Ok(()));
+        request.game_type = TypeOfGame::VsAi(5);
+        assert_eq!(request.check(), Err(ErrorCode::InvalidAiLevel));
+    }
+
+    #[test]
+    fn sub_variant_must_fit_variant() {
+        let mut request = GameRequest::empty();
+        request.sub_variant = SubVariant::try_from(1).ok();
+        assert_eq!(request.check(), Err(ErrorCode::InvalidSubVariant));
+        request.variant = Variant::StandardFairy;
+        assert_eq!(request.check(), Ok(()));
+    }
+
+    #[test]
+    fn open_challenge_has_no_friend() {
+        assert!(!is_friend(&TypeOfGame::VsFriend(String::new())));
+        assert!(is_friend(&TypeOfGame::VsFriend(String::from("friend"))));
+        assert!(!is_friend(&TypeOfGame::VsAi(1)));
+    }
+}
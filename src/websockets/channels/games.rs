@@ -43,6 +43,11 @@ pub enum GamesMessage {
     },
     GetGame(oneshot::Sender<ShuuroGame>, String),
     SaveState,
+    /// Guest logged in and merged games into account.
+    RenamePlayer {
+        from: String,
+        to: String,
+    },
 }
 
 pub async fn games_task(
@@ -164,6 +169,15 @@ pub async fn games_task(
                         let _ = channel.1.send(GameMessage::SaveState).await;
                     }
                 }
+                GamesMessage::RenamePlayer { from, to } => {
+                    for channel in channels.values() {
+                        let message = GameMessage::RenamePlayer {
+                            from: from.clone(),
+                            to: to.clone(),
+                        };
+                        let _ = channel.send(message).await;
+                    }
+                }
             }
         }
     });
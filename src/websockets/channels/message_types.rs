@@ -31,6 +31,16 @@ pub enum MessageType {
     ReloadJinja,
     ConfirmSelection,
     NewPlayer,
+    ChatMessage,
+    RateLimited,
+    Error,
+    Challenge,
+    AcceptChallenge,
+    DeclineChallenge,
+    UnreadNotifications,
+    Presence,
+    FollowedGameStarted,
+    RenamePlayer,
 }
 
 impl MessageType {
@@ -9,9 +9,12 @@ use tv::{tv_task, TvMessage};
 
 use crate::database::Database;
 
+use super::rate_limit::SessionLimits;
+
 pub mod ai;
 pub mod chat;
 pub mod clock;
+pub mod errors;
 pub mod game;
 pub mod game_requests;
 pub mod games;
@@ -28,6 +31,7 @@ pub struct WsState {
     pub games: mpsc::Sender<GamesMessage>,
     pub players: mpsc::Sender<PlayersMessage>,
     pub jinja: mpsc::Sender<JinjaMessage>,
+    pub limits: SessionLimits,
 }
 
 impl WsState {
@@ -35,7 +39,7 @@ impl WsState {
         let tv = tv_task().await;
         let games = games_task(db.clone(), tv.clone()).await;
         let game_requests = game_requests_task(db.clone()).await;
-        let players = players_task().await;
+        let players = players_task(db.clone()).await;
         let jinja = mpsc::channel(200).0;
 
         Self {
@@ -44,6 +48,7 @@ impl WsState {
             game_requests,
             players,
             jinja,
+            limits: SessionLimits::default(),
         }
     }
 
@@ -63,6 +68,7 @@ impl WsState {
             games: mpsc::channel(2).0,
             players: mpsc::channel(2).0,
             jinja: mpsc::channel(2).0,
+            limits: SessionLimits::default(),
         }
     }
 }
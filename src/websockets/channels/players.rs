@@ -1,10 +1,23 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 use typeshare::typeshare;
 
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::{
+    mpsc::{self, Sender},
+    oneshot,
+};
 
-use crate::websockets::handler::WsMessage;
+use crate::{
+    database::{
+        clock::queries::get_followers,
+        model::{Challenge, Sanction, SanctionKind},
+        Database,
+    },
+    websockets::handler::{WsMessage, SESSION_REVOKED},
+};
 
 use super::{
     message_types::MessageType,
@@ -27,12 +40,68 @@ pub enum PlayersMessage {
         game: String,
         player: String,
     },
+    /// New or changed challenge, sent to both players.
+    Challenge(Challenge),
+    UnreadNotifications {
+        player: String,
+        count: u64,
+    },
+    GameStarted {
+        game: String,
+        players: [String; 2],
+    },
+    GameEnded([String; 2]),
+    GetPresence {
+        players: Vec<String>,
+        sender: oneshot::Sender<Vec<PlayerPresence>>,
+    },
+    /// New socket of session.
+    Connected {
+        session: String,
+        sender: Sender<WsMessage>,
+    },
+    /// Close all sockets of revoked session.
+    CloseSession(String),
+    /// Guest merged games into account.
+    RenamePlayer {
+        from: String,
+        to: String,
+    },
+    /// Followers loaded by `notify_followers`.
+    NotifyFollowers {
+        followers: Vec<String>,
+        presence: PlayerPresence,
+        t: MessageType,
+    },
+    /// Active sanctions of online player, `None` if they are not loaded yet.
+    GetSanctions {
+        player: String,
+        sender: oneshot::Sender<Option<Vec<SanctionKind>>>,
+    },
+    /// Sanctions loaded from database after `GetSanctions` returned `None`.
+    CacheSanctions {
+        player: String,
+        sanctions: Vec<Sanction>,
+    },
+    /// Sanction added or lifted by moderator.
+    SanctionsChanged(String),
 }
 
-pub async fn players_task() -> Sender<PlayersMessage> {
+pub async fn players_task(db: Arc<Database>) -> Sender<PlayersMessage> {
     let (sender, mut recv) = mpsc::channel(1024);
+    // Own channel, for results of spawned queries.
+    let send = sender.clone();
     let mut watchers = Watchers::new();
+    // Sockets for each session.
+    let mut sessions = Watchers::new();
     let mut names = HashSet::new();
+    // Current game for each player.
+    let mut current_games: HashMap<String, String> = HashMap::new();
+    // Sanctions of online players, checked on every chat message.
+    let mut sanctions: HashMap<String, Vec<Sanction>> = HashMap::new();
+    // Players whose sanctions are being loaded. Loads started before a
+    // change are not cached.
+    let mut loading = HashSet::new();
     let _ = tokio::spawn(async move {
         let mut _ws = Arc::new(WsState::empty());
         while let Some(message) = recv.recv().await {
@@ -41,6 +110,13 @@ pub async fn players_task() -> Sender<PlayersMessage> {
                     watchers.add_watcher(player.to_string(), sender);
                     if !names.contains(&player) {
                         names.insert(player.to_string());
+                        let presence = PlayerPresence {
+                            game: current_games.get(&player).cloned(),
+                            username: player.to_string(),
+                            online: true,
+                        };
+                        let t = MessageType::Presence;
+                        notify_followers(&db, &send, presence, t);
                     }
                     let msg = PlayersCount {
                         count: names.len() as u64,
@@ -61,6 +137,15 @@ pub async fn players_task() -> Sender<PlayersMessage> {
                     watchers.remove_watcher(&player);
                     if disconnected {
                         names.remove(&player);
+                        sanctions.remove(&player);
+                        loading.remove(&player);
+                        let presence = PlayerPresence {
+                            game: current_games.get(&player).cloned(),
+                            username: player.to_string(),
+                            online: false,
+                        };
+                        let t = MessageType::Presence;
+                        notify_followers(&db, &send, presence, t);
                         let msg = PlayersCount {
                             count: names.len() as u64,
                             t: MessageType::PlayerCount,
@@ -75,6 +160,19 @@ pub async fn players_task() -> Sender<PlayersMessage> {
                     }
                 }
                 PlayersMessage::SetWs(ws_state) => _ws = ws_state,
+                PlayersMessage::Connected { session, sender } => {
+                    for sockets in sessions.players.values_mut() {
+                        sockets.retain(|socket| !socket.is_closed());
+                    }
+                    sessions.players.retain(|_, sockets| !sockets.is_empty());
+                    sessions.add_watcher(session, sender);
+                }
+                PlayersMessage::CloseSession(session) => {
+                    let sockets = sessions.remove_watcher(&session);
+                    for socket in sockets.unwrap_or_default() {
+                        let _ = socket.send(WsMessage::Close(SESSION_REVOKED)).await;
+                    }
+                }
                 PlayersMessage::Redirect { game, player } => {
                     let msg = RedirectPlayer {
                         t: MessageType::RedirectToGame,
@@ -92,6 +190,132 @@ pub async fn players_task() -> Sender<PlayersMessage> {
                         )
                         .await;
                 }
+                PlayersMessage::UnreadNotifications { player, count } => {
+                    let msg = UnreadNotifications::new(count);
+                    let msg = serde_json::json!(msg).to_string();
+
+                    watchers
+                        .notify(
+                            WsMessage::Message(msg),
+                            SendTo::Players {
+                                list: vec![player],
+                                to_others: false,
+                            },
+                        )
+                        .await;
+                }
+                PlayersMessage::GameStarted { game, players } => {
+                    for player in players {
+                        if player == "AI" {
+                            continue;
+                        }
+                        current_games.insert(player.to_string(), game.to_string());
+                        let presence = PlayerPresence {
+                            online: names.contains(&player),
+                            username: player,
+                            game: Some(game.to_string()),
+                        };
+                        let t = MessageType::FollowedGameStarted;
+                        notify_followers(&db, &send, presence, t);
+                    }
+                }
+                PlayersMessage::GameEnded(players) => {
+                    for player in players {
+                        if current_games.remove(&player).is_none() {
+                            continue;
+                        }
+                        let presence = PlayerPresence {
+                            online: names.contains(&player),
+                            username: player,
+                            game: None,
+                        };
+                        let t = MessageType::Presence;
+                        notify_followers(&db, &send, presence, t);
+                    }
+                }
+                PlayersMessage::RenamePlayer { from, to } => {
+                    if let Some(game) = current_games.remove(&from) {
+                        current_games.insert(to, game);
+                    }
+                    sanctions.remove(&from);
+                    loading.remove(&from);
+                }
+                PlayersMessage::NotifyFollowers {
+                    followers,
+                    presence,
+                    t,
+                } => {
+                    let list: Vec<String> = followers
+                        .into_iter()
+                        .filter(|follower| watchers.players.contains_key(follower))
+                        .collect();
+                    if list.is_empty() {
+                        continue;
+                    }
+                    let msg = PresenceUpdate { t, presence };
+                    let msg = serde_json::json!(msg).to_string();
+                    watchers
+                        .notify(
+                            WsMessage::Message(msg),
+                            SendTo::Players {
+                                list,
+                                to_others: false,
+                            },
+                        )
+                        .await;
+                }
+                PlayersMessage::GetPresence { players, sender } => {
+                    let presence = players
+                        .into_iter()
+                        .map(|player| PlayerPresence {
+                            online: names.contains(&player),
+                            game: current_games.get(&player).cloned(),
+                            username: player,
+                        })
+                        .collect();
+                    let _ = sender.send(presence);
+                }
+                PlayersMessage::GetSanctions { player, sender } => {
+                    let now = db.clock.bson_now();
+                    let active = sanctions.get(&player).map(|list| {
+                        list.iter()
+                            .filter(|sanction| sanction.is_active(now))
+                            .map(|sanction| sanction.kind)
+                            .collect()
+                    });
+                    if active.is_none() {
+                        loading.insert(player);
+                    }
+                    let _ = sender.send(active);
+                }
+                PlayersMessage::CacheSanctions {
+                    player,
+                    sanctions: list,
+                } => {
+                    if loading.remove(&player) && names.contains(&player) {
+                        sanctions.insert(player, list);
+                    }
+                }
+                PlayersMessage::SanctionsChanged(player) => {
+                    sanctions.remove(&player);
+                    loading.remove(&player);
+                }
+                PlayersMessage::Challenge(challenge) => {
+                    let list =
+                        vec![challenge.challenger.clone(), challenge.target.clone()];
+                    let msg = ChallengeUpdate::new(challenge);
+                    let msg = serde_json::json!(msg).to_string();
+
+                    watchers
+                        .notify(
+                            WsMessage::Message(msg),
+                            SendTo::Players {
+                                list,
+                                to_others: false,
+                            },
+                        )
+                        .await;
+                }
             };
         }
     });
@@ -99,6 +323,27 @@ pub async fn players_task() -> Sender<PlayersMessage> {
     sender
 }
 
+/// Send presence change to online followers of player. Followers are
+/// loaded outside of players task, so it doesn't wait for database.
+fn notify_followers(
+    db: &Arc<Database>,
+    players: &Sender<PlayersMessage>,
+    presence: PlayerPresence,
+    t: MessageType,
+) {
+    let db = db.clone();
+    let players = players.clone();
+    tokio::spawn(async move {
+        let followers = get_followers(&db.mongo.follows, &presence.username).await;
+        let message = PlayersMessage::NotifyFollowers {
+            followers,
+            presence,
+            t,
+        };
+        let _ = players.send(message).await;
+    });
+}
+
 #[derive(Serialize, Deserialize)]
 #[typeshare]
 pub struct PlayersCount {
@@ -113,3 +358,52 @@ pub struct RedirectPlayer {
     t: MessageType,
     game: String,
 }
+
+#[typeshare]
+#[derive(Serialize, Deserialize)]
+pub struct ChallengeUpdate {
+    t: MessageType,
+    challenge: Challenge,
+}
+
+impl ChallengeUpdate {
+    pub fn new(challenge: Challenge) -> Self {
+        Self {
+            t: MessageType::Challenge,
+            challenge,
+        }
+    }
+}
+
+#[typeshare]
+#[derive(Serialize, Deserialize)]
+pub struct UnreadNotifications {
+    t: MessageType,
+    #[typeshare(serialized_as = "u32")]
+    count: u64,
+}
+
+impl UnreadNotifications {
+    pub fn new(count: u64) -> Self {
+        Self {
+            t: MessageType::UnreadNotifications,
+            count,
+        }
+    }
+}
+
+#[typeshare]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlayerPresence {
+    pub username: String,
+    pub online: bool,
+    /// Game that player is currently playing.
+    pub game: Option<String>,
+}
+
+#[typeshare]
+#[derive(Serialize, Deserialize)]
+pub struct PresenceUpdate {
+    t: MessageType,
+    presence: PlayerPresence,
+}
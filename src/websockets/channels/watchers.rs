@@ -43,6 +43,15 @@ impl Watchers {
         self.players.remove(player)
     }
 
+    /// Sockets of `from` are kept under new name.
+    pub fn rename_watcher(&mut self, from: &String, to: &str) {
+        if let Some(sockets) = self.players.remove(from) {
+            for sender in sockets {
+                self.add_watcher(String::from(to), sender);
+            }
+        }
+    }
+
     pub async fn notify(&self, message: WsMessage, send_to: SendTo) {
         match send_to {
             SendTo::Everyone => {
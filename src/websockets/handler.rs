@@ -1,6 +1,7 @@
 use std::sync::Arc;
+use std::time::Instant;
 
-use axum::extract::ws::{Message, WebSocket};
+use axum::extract::ws::{close_code, CloseFrame, Message, Utf8Bytes, WebSocket};
 use axum::{
     extract::{State, WebSocketUpgrade},
     response::IntoResponse,
@@ -12,23 +13,45 @@ use serde_json::Value;
 use tokio::sync::mpsc::Sender;
 use tokio::sync::{mpsc, oneshot};
 
+use crate::database::clock::queries::{
+    active_sanctions, pending_challenges, unread_notifications,
+};
+use crate::database::model::{Role, SanctionKind};
+use crate::database::roles::has_role;
 use crate::database::Database;
 use crate::{database::redis::UserSession, AppState};
 
+use super::channels::chat::{ChatMessage, MAX_CHAT_LENGTH};
+use super::channels::errors::{ErrorCode, WsError};
 use super::channels::game::GameMessage;
-use super::channels::game_requests::{GameRequest, GameRequestMessage};
+use super::channels::game_requests::{
+    DeclinedChallenge, GameRequest, GameRequestMessage,
+};
 use super::channels::games::GamesMessage;
 use super::channels::message_types::MessageType;
-use super::channels::players::PlayersMessage;
+use super::channels::players::{
+    ChallengeUpdate, PlayersMessage, UnreadNotifications,
+};
 use super::channels::tv::TvMessage;
 use super::channels::WsState;
+use super::rate_limit::{Limit, RateLimited};
 
 pub async fn websocket_handler(
     ws: WebSocketUpgrade,
-    _user_agent: Option<TypedHeader<UserAgent>>,
+    user_agent: Option<TypedHeader<UserAgent>>,
     State(state): State<AppState>,
-    user: UserSession,
+    mut user: UserSession,
 ) -> impl IntoResponse {
+    // Shown in sessions list.
+    match user_agent {
+        Some(TypedHeader(agent)) if agent.as_str() != user.user_agent => {
+            user.user_agent = String::from(agent.as_str());
+            let mut redis = state.db.redis.clone();
+            let session = String::from(&user.session);
+            redis.update_session(&session, &user).await;
+        }
+        _ => (),
+    }
     let headers = &user.headers();
     (
         headers.clone(),
@@ -44,10 +67,25 @@ async fn websocket(
 ) {
     let (mut sender, mut receiver) = stream.split();
     let (player_sender, mut player_recv) = mpsc::channel(20);
+    // Stops receiving after close frame is sent.
+    let (closed_sender, mut closed) = oneshot::channel::<()>();
 
     let socket_send_task = tokio::spawn(async move {
-        while let Some(WsMessage::Message(message)) = player_recv.recv().await {
-            let _ = sender.send(Message::Text(message.into())).await;
+        while let Some(message) = player_recv.recv().await {
+            match message {
+                WsMessage::Message(message) => {
+                    let _ = sender.send(Message::Text(message.into())).await;
+                }
+                WsMessage::Close(reason) => {
+                    let frame = CloseFrame {
+                        code: close_code::POLICY,
+                        reason: Utf8Bytes::from_static(reason),
+                    };
+                    let _ = sender.send(Message::Close(Some(frame))).await;
+                    let _ = closed_sender.send(());
+                    break;
+                }
+            }
         }
     });
 
@@ -61,24 +99,73 @@ async fn websocket(
                 sender: player_sender.clone(),
             })
             .await;
-        while let Some(Ok(message)) = receiver.next().await {
+        let _ = ws
+            .players
+            .send(PlayersMessage::Connected {
+                session: session.session.to_string(),
+                sender: player_sender.clone(),
+            })
+            .await;
+        // Challenges received while offline.
+        let now = db.clock.bson_now();
+        for challenge in
+            pending_challenges(&db.mongo.challenges, &session.username, now).await
+        {
+            let msg = serde_json::json!(ChallengeUpdate::new(challenge)).to_string();
+            let _ = player_sender.send(WsMessage::Message(msg)).await;
+        }
+        let count =
+            unread_notifications(&db.mongo.notifications, &session.username).await;
+        let msg = serde_json::json!(UnreadNotifications::new(count)).to_string();
+        let _ = player_sender.send(WsMessage::Message(msg)).await;
+        loop {
+            let message = tokio::select! {
+                message = receiver.next() => message,
+                _ = &mut closed => break,
+            };
+            let Some(Ok(message)) = message else {
+                break;
+            };
             let Message::Text(message) = message else {
                 socket_send_task.abort();
                 break;
             };
 
-            let Ok(value) = serde_json::from_str::<Value>(&message) else {
-                continue;
-            };
-
-            let Ok(message) = serde_json::from_value::<ClientMessage>(value.clone())
-            else {
+            let Ok(message) = serde_json::from_str::<ClientMessage>(&message) else {
+                // Counted as rejected message, flood of them is not answered.
+                if !ws.limits.strike(&session.session, Instant::now()) {
+                    close_socket(&player_sender, &mut closed, RATE_LIMITED).await;
+                    break;
+                }
+                WsError::new(ErrorCode::InvalidMessage, None)
+                    .send(&player_sender)
+                    .await;
                 continue;
             };
+            match ws.limits.check(&session.session, message.t, Instant::now()) {
+                Limit::Allowed => {}
+                Limit::Limited(retry_in) => {
+                    let msg = RateLimited {
+                        t: MessageType::RateLimited,
+                        message_type: message.t,
+                        retry_in: retry_in.as_millis() as u64,
+                    };
+                    let msg = serde_json::json!(msg).to_string();
+                    let _ = player_sender.send(WsMessage::Message(msg)).await;
+                    continue;
+                }
+                Limit::Disconnect => {
+                    close_socket(&player_sender, &mut closed, RATE_LIMITED).await;
+                    break;
+                }
+            }
             match message.t {
                 MessageType::ChangeRoom => {
                     let Ok(new_room) = serde_json::from_value::<String>(message.d)
                     else {
+                        WsError::new(ErrorCode::InvalidMessage, Some(message.t))
+                            .send(&player_sender)
+                            .await;
                         continue;
                     };
                     let new_room = CurrentRoom::from(new_room);
@@ -164,6 +251,12 @@ async fn websocket(
                                     .await;
                                 current_game = Some(game);
                             } else {
+                                WsError::new(
+                                    ErrorCode::GameNotFound,
+                                    Some(message.t),
+                                )
+                                .send(&player_sender)
+                                .await;
                                 current_game = None;
                             }
                         }
@@ -171,21 +264,72 @@ async fn websocket(
                 }
                 MessageType::AddGameRequest => {
                     if current_room != CurrentRoom::Home {
+                        WsError::new(ErrorCode::WrongRoom, Some(message.t))
+                            .send(&player_sender)
+                            .await;
                         continue;
                     }
                     let Ok(game_request) =
                         serde_json::from_value::<GameRequest>(message.d)
                     else {
+                        WsError::new(ErrorCode::InvalidRequest, Some(message.t))
+                            .send(&player_sender)
+                            .await;
                         continue;
                     };
                     let game_request = GameRequestMessage::AddGameRequest {
                         caller: session.username.to_string(),
                         request: game_request,
+                        socket: player_sender.clone(),
                     };
                     let _ = ws.game_requests.send(game_request).await;
                 }
+                MessageType::AcceptChallenge => {
+                    let Ok(id) = serde_json::from_value::<String>(message.d) else {
+                        WsError::new(ErrorCode::InvalidMessage, Some(message.t))
+                            .send(&player_sender)
+                            .await;
+                        continue;
+                    };
+                    let _ = ws
+                        .game_requests
+                        .send(GameRequestMessage::AcceptChallenge {
+                            id,
+                            player: session.username.to_string(),
+                            socket: player_sender.clone(),
+                        })
+                        .await;
+                }
+                MessageType::DeclineChallenge => {
+                    let Ok(declined) =
+                        serde_json::from_value::<DeclinedChallenge>(message.d)
+                    else {
+                        WsError::new(ErrorCode::InvalidMessage, Some(message.t))
+                            .send(&player_sender)
+                            .await;
+                        continue;
+                    };
+                    let reason = declined
+                        .reason
+                        .map(|reason| {
+                            reason.trim().chars().take(MAX_CHAT_LENGTH).collect()
+                        })
+                        .filter(|reason: &String| !reason.is_empty());
+                    let _ = ws
+                        .game_requests
+                        .send(GameRequestMessage::DeclineChallenge {
+                            id: declined.id,
+                            player: session.username.to_string(),
+                            reason,
+                            socket: player_sender.clone(),
+                        })
+                        .await;
+                }
                 MessageType::GetHand => {
                     let Some(ref game) = current_game else {
+                        WsError::new(ErrorCode::NotInGame, Some(message.t))
+                            .send(&player_sender)
+                            .await;
                         continue;
                     };
                     let _ = game
@@ -197,10 +341,16 @@ async fn websocket(
                 | MessageType::MovePiece
                 | MessageType::ConfirmSelection => {
                     let Some(ref game) = current_game else {
+                        WsError::new(ErrorCode::NotInGame, Some(message.t))
+                            .send(&player_sender)
+                            .await;
                         continue;
                     };
                     let Ok(game_move) = serde_json::from_value::<String>(message.d)
                     else {
+                        WsError::new(ErrorCode::InvalidMessage, Some(message.t))
+                            .send(&player_sender)
+                            .await;
                         continue;
                     };
 
@@ -208,11 +358,15 @@ async fn websocket(
                         .send(GameMessage::GameMove {
                             player: session.username.to_string(),
                             game_move,
+                            socket: Some(player_sender.clone()),
                         })
                         .await;
                 }
                 MessageType::Draw => {
                     let Some(ref game) = current_game else {
+                        WsError::new(ErrorCode::NotInGame, Some(message.t))
+                            .send(&player_sender)
+                            .await;
                         continue;
                     };
                     let _ = game
@@ -221,14 +375,56 @@ async fn websocket(
                 }
                 MessageType::Resign => {
                     let Some(ref game) = current_game else {
+                        WsError::new(ErrorCode::NotInGame, Some(message.t))
+                            .send(&player_sender)
+                            .await;
                         continue;
                     };
                     let _ = game
                         .send(GameMessage::Resign(session.username.to_string()))
                         .await;
                 }
+                MessageType::ChatMessage => {
+                    let Some(ref game) = current_game else {
+                        WsError::new(ErrorCode::NotInGame, Some(message.t))
+                            .send(&player_sender)
+                            .await;
+                        continue;
+                    };
+                    let Ok(text) = serde_json::from_value::<String>(message.d)
+                    else {
+                        WsError::new(ErrorCode::InvalidMessage, Some(message.t))
+                            .send(&player_sender)
+                            .await;
+                        continue;
+                    };
+                    let text = text.trim();
+                    if text.is_empty() || text.chars().count() > MAX_CHAT_LENGTH {
+                        WsError::new(ErrorCode::InvalidMessage, Some(message.t))
+                            .send(&player_sender)
+                            .await;
+                        continue;
+                    }
+                    let sanctions =
+                        chat_sanctions(&db, &ws, &session.username).await;
+                    if sanctions.contains(&SanctionKind::Mute) {
+                        WsError::new(ErrorCode::Muted, Some(message.t))
+                            .send(&player_sender)
+                            .await;
+                        continue;
+                    }
+                    let _ = game
+                        .send(GameMessage::Chat {
+                            message: ChatMessage::new(&session.username, text),
+                            shadow: sanctions.contains(&SanctionKind::ShadowBan),
+                        })
+                        .await;
+                }
                 MessageType::GetTv => {
                     if current_room != CurrentRoom::Tv {
+                        WsError::new(ErrorCode::WrongRoom, Some(message.t))
+                            .send(&player_sender)
+                            .await;
                         continue;
                     }
 
@@ -238,9 +434,13 @@ async fn websocket(
                         .await;
                 }
                 MessageType::SaveState => {
-                    if &session.username != &db.mod1 {
+                    if !has_role(&db.mongo.players, &session, Role::Admin).await {
+                        WsError::new(ErrorCode::Forbidden, Some(message.t))
+                            .send(&player_sender)
+                            .await;
                         continue;
                     }
+                    // Saving is done by admin route `save_state`.
                 }
                 _ => {}
             }
@@ -279,6 +479,45 @@ async fn websocket(
     });
 }
 
+/// Send close frame and wait until it's sent.
+async fn close_socket(
+    sender: &Sender<WsMessage>,
+    closed: &mut oneshot::Receiver<()>,
+    reason: &'static str,
+) {
+    let _ = sender.send(WsMessage::Close(reason)).await;
+    let _ = closed.await;
+}
+
+/// Active sanctions of player. Database is queried only when players task
+/// has none cached, i.e. on first message or after moderator changed them.
+async fn chat_sanctions(
+    db: &Database,
+    ws: &WsState,
+    username: &str,
+) -> Vec<SanctionKind> {
+    let player = String::from(username);
+    let (sender, recv) = oneshot::channel();
+    let _ = ws
+        .players
+        .send(PlayersMessage::GetSanctions {
+            player: player.clone(),
+            sender,
+        })
+        .await;
+    if let Ok(Some(kinds)) = recv.await {
+        return kinds;
+    }
+    let now = db.clock.bson_now();
+    let sanctions = active_sanctions(&db.mongo.sanctions, username, now).await;
+    let kinds = sanctions.iter().map(|sanction| sanction.kind).collect();
+    let _ = ws
+        .players
+        .send(PlayersMessage::CacheSanctions { player, sanctions })
+        .await;
+    kinds
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ClientMessage {
     pub t: MessageType,
@@ -315,4 +554,11 @@ impl CurrentRoom {}
 #[derive(Clone)]
 pub enum WsMessage {
     Message(String),
+    /// Close frame with reason, nothing is received from socket after it.
+    Close(&'static str),
 }
+
+/// Close reason for sockets of revoked session.
+pub const SESSION_REVOKED: &str = "session revoked";
+/// Close reason after too many rate limited messages.
+pub const RATE_LIMITED: &str = "rate limited";
@@ -1,2 +1,3 @@
 pub mod channels;
 pub mod handler;
+pub mod rate_limit;
@@ -0,0 +1,262 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use typeshare::typeshare;
+
+use super::channels::message_types::MessageType;
+
+/// Messages allowed for one socket, all types together.
+const SESSION_LIMIT: (u32, f64) = (60, 20.0);
+/// Rejected messages before socket is closed, one is forgiven every 10 seconds.
+const STRIKES_LIMIT: (u32, f64) = (10, 0.1);
+/// Unused limiter is removed after this time, all its buckets are full again.
+const IDLE_LIMITER: Duration = Duration::from_secs(10 * 60);
+
+/// Burst size and refill rate per second for each message type.
+fn limits(t: MessageType) -> (u32, f64) {
+    match t {
+        MessageType::AddGameRequest => (3, 0.2),
+        MessageType::GetTv => (2, 0.5),
+        MessageType::GetHand => (5, 1.0),
+        MessageType::ChangeRoom => (10, 2.0),
+        MessageType::ChatMessage => (5, 0.5),
+        MessageType::SelectMove
+        | MessageType::PlacePiece
+        | MessageType::MovePiece
+        | MessageType::ConfirmSelection => (30, 10.0),
+        MessageType::Draw | MessageType::Resign => (5, 1.0),
+        _ => (10, 2.0),
+    }
+}
+
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: u32, refill: f64, now: Instant) -> Self {
+        Self {
+            capacity: capacity as f64,
+            tokens: capacity as f64,
+            refill,
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill).min(self.capacity);
+        self.last = now;
+    }
+
+    pub fn take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return true;
+        }
+        false
+    }
+
+    /// Time until next token.
+    pub fn retry_in(&self) -> Duration {
+        if self.tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((1.0 - self.tokens) / self.refill)
+    }
+}
+
+pub enum Limit {
+    Allowed,
+    Limited(Duration),
+    Disconnect,
+}
+
+/// Limits for one session, all its sockets together.
+pub struct RateLimiter {
+    session: TokenBucket,
+    types: HashMap<usize, TokenBucket>,
+    strikes: TokenBucket,
+    last_check: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            session: TokenBucket::new(SESSION_LIMIT.0, SESSION_LIMIT.1, now),
+            types: HashMap::new(),
+            strikes: TokenBucket::new(STRIKES_LIMIT.0, STRIKES_LIMIT.1, now),
+            last_check: now,
+        }
+    }
+
+    pub fn check(&mut self, t: MessageType, now: Instant) -> Limit {
+        self.last_check = now;
+        let bucket = self.types.entry(t.into_number()).or_insert_with(|| {
+            let (capacity, refill) = limits(t);
+            TokenBucket::new(capacity, refill, now)
+        });
+        let retry_in = if !bucket.take(now) {
+            bucket.retry_in()
+        } else if !self.session.take(now) {
+            self.session.retry_in()
+        } else {
+            return Limit::Allowed;
+        };
+        if !self.strikes.take(now) {
+            return Limit::Disconnect;
+        }
+        Limit::Limited(retry_in)
+    }
+}
+
+/// Limiters of all sessions, reconnecting doesn't reset them.
+#[derive(Clone, Default)]
+pub struct SessionLimits {
+    limiters: Arc<Mutex<HashMap<String, RateLimiter>>>,
+}
+
+impl SessionLimits {
+    pub fn check(&self, session: &str, t: MessageType, now: Instant) -> Limit {
+        let mut limiters = self.limiters.lock().unwrap();
+        if !limiters.contains_key(session) {
+            limiters.retain(|_, limiter| {
+                now.saturating_duration_since(limiter.last_check) < IDLE_LIMITER
+            });
+        }
+        limiters
+            .entry(String::from(session))
+            .or_insert_with(|| RateLimiter::new(now))
+            .check(t, now)
+    }
+
+    /// Invalid message is counted as rejected one. Returns `false` if socket
+    /// should be closed.
+    pub fn strike(&self, session: &str, now: Instant) -> bool {
+        let mut limiters = self.limiters.lock().unwrap();
+        let limiter = limiters
+            .entry(String::from(session))
+            .or_insert_with(|| RateLimiter::new(now));
+        limiter.last_check = now;
+        limiter.strikes.take(now)
+    }
+}
+
+/// Sent when message is dropped because of rate limit.
+#[derive(Serialize)]
+#[typeshare]
+pub struct RateLimited {
+    pub t: MessageType,
+    pub message_type: MessageType,
+    /// Milliseconds until same message is accepted again.
+    #[typeshare(serialized_as = "u32")]
+    pub retry_in: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(limit: Limit) -> bool {
+        matches!(limit, Limit::Allowed)
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(2, 0.5, now);
+        assert!(bucket.take(now));
+        assert!(bucket.take(now));
+        assert!(!bucket.take(now));
+        assert_eq!(bucket.retry_in(), Duration::from_secs(2));
+        assert!(!bucket.take(now + Duration::from_secs(1)));
+        assert!(bucket.take(now + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn types_are_limited_separately() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(now);
+        for _ in 0..3 {
+            assert!(allowed(limiter.check(MessageType::AddGameRequest, now)));
+        }
+        assert!(matches!(
+            limiter.check(MessageType::AddGameRequest, now),
+            Limit::Limited(_)
+        ));
+        assert!(allowed(limiter.check(MessageType::GetHand, now)));
+    }
+
+    #[test]
+    fn session_limit_covers_all_types() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(now);
+        let types = [
+            MessageType::SelectMove,
+            MessageType::PlacePiece,
+            MessageType::MovePiece,
+        ];
+        for i in 0..SESSION_LIMIT.0 {
+            let t = types[i as usize % 3];
+            assert!(allowed(limiter.check(t, now)));
+        }
+        assert!(!allowed(limiter.check(MessageType::GetHand, now)));
+    }
+
+    #[test]
+    fn reconnect_keeps_strikes() {
+        let now = Instant::now();
+        let limits = SessionLimits::default();
+        for _ in 0..2 + STRIKES_LIMIT.0 {
+            limits.check("a", MessageType::GetTv, now);
+        }
+        assert!(matches!(
+            limits.check("a", MessageType::GetTv, now),
+            Limit::Disconnect
+        ));
+        // New socket of same session.
+        assert!(matches!(
+            limits.check("a", MessageType::GetTv, now),
+            Limit::Disconnect
+        ));
+        assert!(allowed(limits.check("b", MessageType::GetTv, now)));
+    }
+
+    #[test]
+    fn invalid_messages_are_strikes() {
+        let now = Instant::now();
+        let limits = SessionLimits::default();
+        for _ in 0..STRIKES_LIMIT.0 {
+            assert!(limits.strike("a", now));
+        }
+        assert!(!limits.strike("a", now));
+        assert!(allowed(limits.check("a", MessageType::GetTv, now)));
+    }
+
+    #[test]
+    fn repeat_offender_is_disconnected() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(now);
+        for _ in 0..2 {
+            limiter.check(MessageType::GetTv, now);
+        }
+        for _ in 0..STRIKES_LIMIT.0 {
+            assert!(matches!(
+                limiter.check(MessageType::GetTv, now),
+                Limit::Limited(_)
+            ));
+        }
+        assert!(matches!(
+            limiter.check(MessageType::GetTv, now),
+            Limit::Disconnect
+        ));
+    }
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use common::{vs_friend, TestServer};
+use lishuuro::websockets::channels::{
+    errors::ErrorCode, message_types::MessageType,
+};
+use serde_json::json;
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn blocked_player_cannot_challenge() {
+    let server = TestServer::start().await;
+    let mut white = server.client().await;
+    let black = server.client().await;
+
+    let res = reqwest::Client::new()
+        .post(format!("http://{}/vue/block/{}", server.addr, white.username))
+        .header("cookie", &black.cookie)
+        .send()
+        .await
+        .unwrap();
+    assert!(res.status().is_success());
+
+    white.change_room("home").await;
+    white.add_game_request(vs_friend(&black.username)).await;
+    let error = white.expect(MessageType::Error).await;
+    assert_eq!(error["code"], json!(ErrorCode::Blocked));
+    server.stop().await;
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use common::{challenge, TestServer};
+use lishuuro::websockets::channels::{
+    errors::ErrorCode, message_types::MessageType,
+};
+use serde_json::json;
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn accepted_challenge_redirects_both_players() {
+    let server = TestServer::start().await;
+    let mut white = server.client().await;
+    let mut black = server.client().await;
+    let id = challenge(&mut white, &mut black).await;
+
+    black.accept_challenge(&id).await;
+    let accepted = white.expect(MessageType::Challenge).await;
+    assert_eq!(accepted["challenge"]["status"], json!("accepted"));
+    let game = accepted["challenge"]["game"].clone();
+    for client in [&mut white, &mut black] {
+        let redirect = client.expect(MessageType::RedirectToGame).await;
+        assert_eq!(redirect["game"], game);
+    }
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn declined_challenge_has_reason() {
+    let server = TestServer::start().await;
+    let mut white = server.client().await;
+    let mut black = server.client().await;
+    let id = challenge(&mut white, &mut black).await;
+
+    black.decline_challenge(&id, "  later  ").await;
+    let declined = white.expect(MessageType::Challenge).await;
+    assert_eq!(declined["challenge"]["status"], json!("declined"));
+    assert_eq!(declined["challenge"]["reason"], json!("later"));
+
+    black.accept_challenge(&id).await;
+    let error = black.expect(MessageType::Error).await;
+    assert_eq!(error["code"], json!(ErrorCode::ChallengeNotFound));
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn only_target_can_accept() {
+    let server = TestServer::start().await;
+    let mut white = server.client().await;
+    let mut black = server.client().await;
+    let id = challenge(&mut white, &mut black).await;
+
+    white.accept_challenge(&id).await;
+    let error = white.expect(MessageType::Error).await;
+    assert_eq!(error["code"], json!(ErrorCode::ChallengeNotFound));
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn challenge_creates_notification() {
+    let server = TestServer::start().await;
+    let mut white = server.client().await;
+    let mut black = server.client().await;
+    challenge(&mut white, &mut black).await;
+
+    let unread = black.expect(MessageType::UnreadNotifications).await;
+    assert_eq!(unread["count"], json!(1));
+
+    let http = reqwest::Client::new();
+    let notifications = http
+        .get(format!("http://{}/vue/notifications", server.addr))
+        .header("cookie", &black.cookie)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(notifications[0]["kind"]["type"], json!("Challenge"));
+    assert_eq!(
+        notifications[0]["kind"]["content"]["challenger"],
+        json!(&white.username)
+    );
+
+    let count = http
+        .post(format!("http://{}/vue/notifications/read", server.addr))
+        .header("cookie", &black.cookie)
+        .json(&json!({}))
+        .send()
+        .await
+        .unwrap()
+        .json::<u64>()
+        .await
+        .unwrap();
+    assert_eq!(count, 0);
+
+    // Empty list marks all too.
+    let mut other = server.client().await;
+    challenge(&mut other, &mut black).await;
+    let count = http
+        .post(format!("http://{}/vue/notifications/read", server.addr))
+        .header("cookie", &black.cookie)
+        .json(&json!({"ids": []}))
+        .send()
+        .await
+        .unwrap()
+        .json::<u64>()
+        .await
+        .unwrap();
+    assert_eq!(count, 0);
+    server.stop().await;
+}
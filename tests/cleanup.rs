@@ -0,0 +1,60 @@
+mod common;
+
+use bson::{doc, DateTime};
+use common::TestServer;
+use lishuuro::database::cleanup::{cleanup_anons, CleanupReport, ANONYMIZED};
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn expired_anons_are_deleted_or_anonymized() {
+    let server = TestServer::start().await;
+    let mongo = &server.state.db.mongo;
+    let users = server.raw_players();
+    let games = server.raw_games();
+    let old = DateTime::from_millis(DateTime::now().timestamp_millis() - 60_000);
+    let players = [
+        ("Anon-a", false),
+        ("Anon-b", false),
+        ("Anon-c", false),
+        ("Anon-d", false),
+        ("reg", true),
+    ];
+    server.seed_players(&players, old).await;
+    server
+        .seed_games(&[
+            ("finished", ["Anon-b", "reg"], 7),
+            ("unfinished", ["Anon-a", "reg"], -1),
+            ("aborted", ["Anon-d", "reg"], 10),
+        ])
+        .await;
+
+    let now = DateTime::now();
+    let report = cleanup_anons(mongo, now, false).await;
+    let expected = CleanupReport {
+        deleted: 1,
+        anonymized: 0,
+        kept: 3,
+    };
+    assert_eq!(report, expected);
+
+    let report = cleanup_anons(mongo, now, true).await;
+    let expected = CleanupReport {
+        deleted: 0,
+        anonymized: 2,
+        kept: 1,
+    };
+    assert_eq!(report, expected);
+    // Player of unfinished game is kept with their name in game.
+    let left = users.count_documents(doc! {"reg": false}).await.unwrap();
+    assert_eq!(left, 1);
+    let game = games.find_one(doc! {"_id": "unfinished"}).await.unwrap();
+    let players = game.unwrap().get_array("players").unwrap().clone();
+    assert_eq!(players[0].as_str(), Some("Anon-a"));
+    for id in ["finished", "aborted"] {
+        let game = games.find_one(doc! {"_id": id}).await.unwrap().unwrap();
+        let players = game.get_array("players").unwrap();
+        assert_eq!(players[0].as_str(), Some(ANONYMIZED));
+        assert_eq!(players[1].as_str(), Some("reg"));
+    }
+    server.stop().await;
+}
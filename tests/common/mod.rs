@@ -0,0 +1,446 @@
+#![allow(dead_code)]
+
+use std::{env, net::SocketAddr, sync::Arc, time::Duration};
+
+use bson::{doc, DateTime, Document};
+use futures::{SinkExt, StreamExt};
+use hyper::header::{COOKIE, SET_COOKIE};
+use lishuuro::{
+    app,
+    config::RawConfig,
+    database::{
+        clock::time_source::{system_clock, Clock},
+        indexes::ensure_indexes,
+        model::Mongo,
+        redis::RedisCli,
+        Database,
+    },
+    lichess::MyKey,
+    websockets::{
+        channels::{ai::Pockets, message_types::MessageType, WsState},
+        handler::ClientMessage,
+    },
+    AppState,
+};
+use minijinja::Environment;
+use mongodb::{Client, Collection};
+use serde_json::{json, Value};
+use shuuro::{
+    position::{Board, Outcome, Placement, Play, Rules, Sfen},
+    shuuro8::{bitboard8::BB8, position8::P8, square8::Square8},
+    Color, Move, Piece, PieceType, Variant,
+};
+use tokio::{net::TcpStream, time::timeout};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, http::HeaderValue, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+
+/// How long a client waits for one message before the test fails.
+const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Server running on ephemeral port.
+///
+/// Sessions are kept in memory. Games and players are stored in a fresh
+/// MongoDB database (`MONGO_TEST`), which is dropped by `stop`.
+pub struct TestServer {
+    pub addr: SocketAddr,
+    pub state: AppState,
+    mongo_addr: String,
+    db_name: String,
+}
+
+impl TestServer {
+    /// Panics when `MONGO_TEST` is not set or not reachable, tests that use
+    /// it are ignored by default.
+    pub async fn start() -> Self {
+        Self::start_with_clock(system_clock()).await
+    }
+
+    /// Same as `start`, games and sessions use `clock`.
+    pub async fn start_with_clock(clock: Clock) -> Self {
+        let mongo_addr = env::var("MONGO_TEST")
+            .expect("MONGO_TEST must be set to run tests with --ignored");
+        let client = Client::with_uri_str(&mongo_addr).await.unwrap();
+        if let Err(e) = client.database("admin").run_command(doc! {"ping": 1}).await
+        {
+            panic!("mongo at {} is not reachable: {}", mongo_addr, e);
+        }
+
+        let db_name = format!("lishuuro_test_{}", uuid::Uuid::new_v4().simple());
+        let mut raw = RawConfig::default();
+        raw.mongo = Some(String::from(&mongo_addr));
+        raw.mongo_db = Some(String::from(&db_name));
+        raw.redis = Some(String::from("redis://unused"));
+        raw.login_state = Some(String::from("test"));
+        raw.bind = Some(String::from("127.0.0.1:0"));
+        let config = Arc::new(raw.validate().unwrap());
+
+        let mongo = Mongo::new(&config.mongo, &config.mongo_db).await;
+        ensure_indexes(&mongo).await.unwrap();
+        let db = Arc::new(Database {
+            redis: RedisCli::memory(),
+            mongo,
+            key: MyKey::from(config.as_ref()),
+            pockets: Arc::new(Pockets::new(&config.pockets)),
+            clock,
+        });
+        let ws = Arc::new(WsState::new(db.clone()).await);
+        ws.send_ws(ws.clone()).await;
+        let state = AppState {
+            db,
+            ws,
+            jinja: Arc::new(Environment::new()),
+            config: config.clone(),
+        };
+
+        let listener = tokio::net::TcpListener::bind(config.bind).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = app(state.clone());
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        Self {
+            addr,
+            state,
+            mongo_addr,
+            db_name,
+        }
+    }
+
+    /// Create new anonymous session and open websocket with it.
+    pub async fn client(&self) -> TestClient {
+        let res = reqwest::Client::new()
+            .get(format!("http://{}/vue_user", self.addr))
+            .header(COOKIE, "axum_session=new")
+            .send()
+            .await
+            .unwrap();
+        let cookie = res
+            .headers()
+            .get(SET_COOKIE)
+            .expect("new session must set cookie")
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+        let user = res.json::<Value>().await.unwrap();
+        let username = user["username"].as_str().unwrap().to_string();
+        self.connect(cookie, username).await
+    }
+
+    /// Open another websocket with existing session.
+    pub async fn connect(&self, cookie: String, username: String) -> TestClient {
+        let mut request = format!("ws://{}/ws/", self.addr)
+            .into_client_request()
+            .unwrap();
+        request
+            .headers_mut()
+            .insert(COOKIE, HeaderValue::from_str(&cookie).unwrap());
+        let (socket, _) = connect_async(request).await.unwrap();
+
+        TestClient {
+            username,
+            cookie,
+            socket,
+        }
+    }
+
+    /// Games without `ShuuroGame` defaults, for seeding and checking fields
+    /// as they are stored.
+    pub fn raw_games(&self) -> Collection<Document> {
+        self.state.db.mongo.games.clone_with_type::<Document>()
+    }
+
+    pub fn raw_players(&self) -> Collection<Document> {
+        self.state.db.mongo.players.clone_with_type::<Document>()
+    }
+
+    /// Insert games with only id, players and status.
+    pub async fn seed_games(&self, games: &[(&str, [&str; 2], i32)]) {
+        let games = games.iter().map(|&(id, players, status)| {
+            doc! {"_id": id, "players": players.to_vec(), "status": status}
+        });
+        self.raw_games().insert_many(games).await.unwrap();
+    }
+
+    /// Insert players by name and `reg`, all created at same time.
+    pub async fn seed_players(
+        &self,
+        players: &[(&str, bool)],
+        created_at: DateTime,
+    ) {
+        let players = players.iter().map(|&(name, reg)| {
+            doc! {"_id": name, "reg": reg, "created_at": created_at}
+        });
+        self.raw_players().insert_many(players).await.unwrap();
+    }
+
+    /// Session of `client` after lichess login as `username`, guest games can
+    /// be merged.
+    pub async fn login(&self, client: &TestClient, username: &str) {
+        let mut redis = self.state.db.redis.clone();
+        let key = client.session().to_string();
+        let now = self.state.db.clock.bson_now();
+        let mut user = redis.get_session(&key, now).await.unwrap();
+        user.username = String::from(username);
+        user.reg = true;
+        user.merge_from = Some(client.username.clone());
+        redis.update_session(&key, &user).await;
+    }
+
+    pub async fn stop(self) {
+        let client = Client::with_uri_str(&self.mongo_addr).await.unwrap();
+        let _ = client.database(&self.db_name).drop().await;
+    }
+}
+
+/// One websocket connection with its own session.
+pub struct TestClient {
+    pub username: String,
+    pub cookie: String,
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl TestClient {
+    /// Key of session in Redis.
+    pub fn session(&self) -> &str {
+        self.cookie.split_once('=').unwrap().1
+    }
+
+    pub async fn send(&mut self, t: MessageType, d: Value) {
+        let message = serde_json::to_string(&ClientMessage { t, d }).unwrap();
+        self.socket.send(Message::text(message)).await.unwrap();
+    }
+
+    /// Like `send`, but server may have closed socket already.
+    pub async fn try_send(&mut self, t: MessageType, d: Value) -> bool {
+        let message = serde_json::to_string(&ClientMessage { t, d }).unwrap();
+        self.socket.send(Message::text(message)).await.is_ok()
+    }
+
+    pub async fn change_room(&mut self, room: &str) {
+        self.send(MessageType::ChangeRoom, json!(room)).await;
+    }
+
+    pub async fn add_game_request(&mut self, request: Value) {
+        self.send(MessageType::AddGameRequest, request).await;
+    }
+
+    pub async fn select_move(&mut self, game_move: &str) {
+        self.send(MessageType::SelectMove, json!(game_move)).await;
+    }
+
+    pub async fn place_piece(&mut self, game_move: &str) {
+        self.send(MessageType::PlacePiece, json!(game_move)).await;
+    }
+
+    pub async fn move_piece(&mut self, game_move: &str) {
+        self.send(MessageType::MovePiece, json!(game_move)).await;
+    }
+
+    pub async fn accept_challenge(&mut self, id: &str) {
+        self.send(MessageType::AcceptChallenge, json!(id)).await;
+    }
+
+    pub async fn decline_challenge(&mut self, id: &str, reason: &str) {
+        let d = json!({"id": id, "reason": reason});
+        self.send(MessageType::DeclineChallenge, d).await;
+    }
+
+    pub async fn resign(&mut self) {
+        self.send(MessageType::Resign, json!("")).await;
+    }
+
+    /// Next JSON message from server.
+    pub async fn recv(&mut self) -> Value {
+        loop {
+            let message = timeout(RECV_TIMEOUT, self.socket.next())
+                .await
+                .unwrap_or_else(|_| panic!("{}: no message received", self.username))
+                .expect("socket closed")
+                .unwrap();
+            if let Message::Text(text) = message {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    /// Skip messages until server closes socket.
+    pub async fn expect_close(&mut self) {
+        loop {
+            let message = timeout(RECV_TIMEOUT, self.socket.next())
+                .await
+                .unwrap_or_else(|_| panic!("{}: socket not closed", self.username));
+            match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+
+    /// Skip messages until one with given type arrives.
+    pub async fn expect(&mut self, t: MessageType) -> Value {
+        loop {
+            let message = self.recv().await;
+            if message["t"] == json!(t.into_number()) {
+                return message;
+            }
+        }
+    }
+}
+
+/// Game request against named player, caller plays white.
+pub fn vs_friend(name: &str) -> Value {
+    json!({
+        "minutes": 3,
+        "incr": 2,
+        "variant": 2,
+        "sub_variant": 100,
+        "color": 0,
+        "game_type": {"type": "VsFriend", "content": name}
+    })
+}
+
+/// Sends challenge from one client to other and returns its id.
+pub async fn challenge(from: &mut TestClient, to: &mut TestClient) -> String {
+    from.change_room("home").await;
+    from.add_game_request(vs_friend(&to.username)).await;
+    // Both players get pending challenge.
+    from.expect(MessageType::Challenge).await;
+    let challenge = to.expect(MessageType::Challenge).await;
+    challenge["challenge"]["_id"].as_str().unwrap().to_string()
+}
+
+/// Creates game between two clients and waits until clock starts.
+pub async fn start_game(white: &mut TestClient, black: &mut TestClient) -> String {
+    let challenge = challenge(white, black).await;
+    black.accept_challenge(&challenge).await;
+    let redirect = white.expect(MessageType::RedirectToGame).await;
+    let id = redirect["game"].as_str().unwrap().to_string();
+    let room = format!("/game/{}", &id);
+    white.change_room(&room).await;
+    black.change_room(&room).await;
+    let start = white.expect(MessageType::StartClock).await;
+    assert_eq!(start["players"], json!([&white.username, &black.username]));
+    id
+}
+
+/// Position of game started by `start_game`.
+pub type Position = P8<Square8, BB8<Square8>>;
+
+pub fn position(sfen: &str) -> Position {
+    let mut position = Position::new();
+    position.update_variant(Variant::Standard);
+    position.set_sfen(sfen).unwrap();
+    position
+}
+
+/// Both players buy queen, returns sfen of placement start.
+pub async fn select_queens(
+    white: &mut TestClient,
+    black: &mut TestClient,
+) -> String {
+    white.select_move("+Q").await;
+    white.select_move("c").await;
+    black.select_move("+q").await;
+    black.select_move("c").await;
+    let redirect = white.expect(MessageType::RedirectToGame).await;
+    redirect["sfen"].as_str().unwrap().to_string()
+}
+
+/// Places all pieces, returns each move with sfen after it.
+pub async fn play_placement(
+    white: &mut TestClient,
+    black: &mut TestClient,
+    placement: &mut Position,
+) -> Vec<(String, String)> {
+    let mut moves = vec![];
+    loop {
+        let player = mover(placement, white, black);
+        let (game_move, sfen) = next_placement(placement);
+        player.place_piece(&game_move).await;
+        moves.push((game_move, sfen));
+        let placed = white.expect(MessageType::PlacePiece).await;
+        black.expect(MessageType::PlacePiece).await;
+        assert_eq!(placed["first_move_error"], json!(false));
+        if placed["next_stage"] == json!(true) {
+            return moves;
+        }
+    }
+}
+
+/// Plays `count` fight moves, returns each move with sfen after it.
+pub async fn play_fight(
+    white: &mut TestClient,
+    black: &mut TestClient,
+    fight: &mut Position,
+    count: usize,
+) -> Vec<(String, String)> {
+    let mut moves = vec![];
+    for _ in 0..count {
+        let player = mover(fight, white, black);
+        let (game_move, sfen) = next_move(fight);
+        player.move_piece(&game_move).await;
+        moves.push((game_move, sfen));
+        white.expect(MessageType::MovePiece).await;
+        black.expect(MessageType::MovePiece).await;
+    }
+    moves
+}
+
+/// Client playing side to move.
+pub fn mover<'a>(
+    position: &Position,
+    white: &'a mut TestClient,
+    black: &'a mut TestClient,
+) -> &'a mut TestClient {
+    match position.side_to_move() {
+        Color::White => white,
+        _ => black,
+    }
+}
+
+/// Placement that doesn't put any king in check.
+pub fn next_placement(position: &mut Position) -> (String, String) {
+    let color = position.side_to_move();
+    for (&key, squares) in position.get_placement_squares().iter() {
+        let piece = Piece {
+            piece_type: PieceType::try_from(key).unwrap(),
+            color,
+        };
+        for sq in squares.into_iter() {
+            let mut next = position.clone();
+            let Some(sfen) = next.place(piece, sq) else {
+                continue;
+            };
+            if next.in_check(Color::White) || next.in_check(Color::Black) {
+                continue;
+            }
+            *position = next;
+            return (format!("{}@{}", piece, sq), sfen.to_string());
+        }
+    }
+    panic!("no placement for {:?}", color);
+}
+
+/// Fight move that doesn't end game.
+fn next_move(position: &mut Position) -> (String, String) {
+    let color = position.side_to_move();
+    for (from, targets) in position.legal_moves(color) {
+        for to in targets {
+            let game_move = format!("{}_{}", from, to);
+            let m = Move::<Square8>::from_sfen(&game_move).unwrap();
+            let mut next = position.clone();
+            if let Ok(Outcome::MoveOk) = next.make_move(m) {
+                *position = next;
+                return (game_move, position.generate_sfen());
+            }
+        }
+    }
+    panic!("no move for {:?}", color);
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use bson::{doc, DateTime};
+use common::TestServer;
+use lishuuro::database::crosstable::{crosstable, Streak};
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn crosstable_is_totaled_per_variant() {
+    let server = TestServer::start().await;
+    let games = &server.state.db.mongo.games;
+    let raw = server.raw_games();
+    let now = DateTime::now().timestamp_millis();
+    for (id, variant, players, status, result, age) in [
+        ("draw", 4, ["a", "b"], 5, 2, 4),
+        ("resign", 2, ["a", "b"], 7, 1, 3),
+        ("mate", 2, ["b", "a"], 1, 0, 2),
+        ("latest", 2, ["a", "b"], 1, 1, 1),
+        ("other", 2, ["a", "c"], 1, 0, 0),
+        ("live", 2, ["a", "b"], -1, 2, 0),
+    ] {
+        let game = doc! {
+            "_id": id,
+            "variant": variant,
+            "players": players.to_vec(),
+            "status": status,
+            "result": result,
+            "last_clock": DateTime::from_millis(now - age * 1000)
+        };
+        raw.insert_one(game).await.unwrap();
+    }
+
+    let table = crosstable(games, "a", "b", 2).await;
+    assert_eq!(table.score.wins, 1);
+    assert_eq!(table.score.losses, 2);
+    assert_eq!(table.score.draws, 1);
+    assert_eq!(table.variants.len(), 2);
+    assert_eq!(table.variants[0].variant, 2);
+    assert_eq!(table.variants[1].score.draws, 1);
+    let ids: Vec<&str> = table.games.iter().map(|game| game._id.as_str()).collect();
+    assert_eq!(ids, ["latest", "mate"]);
+    let streak = Streak {
+        username: String::from("b"),
+        count: 2,
+    };
+    assert_eq!(table.streak, Some(streak));
+    server.stop().await;
+}
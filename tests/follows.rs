@@ -0,0 +1,45 @@
+mod common;
+
+use common::{start_game, TestServer};
+use lishuuro::websockets::channels::message_types::MessageType;
+use serde_json::{json, Value};
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn follower_sees_game_start_and_presence() {
+    let server = TestServer::start().await;
+    let mut fan = server.client().await;
+    let mut white = server.client().await;
+    let mut black = server.client().await;
+
+    let http = reqwest::Client::new();
+    let res = http
+        .post(format!("http://{}/vue/follow/{}", server.addr, white.username))
+        .header("cookie", &fan.cookie)
+        .send()
+        .await
+        .unwrap();
+    assert!(res.status().is_success());
+
+    let id = start_game(&mut white, &mut black).await;
+    let started = fan.expect(MessageType::FollowedGameStarted).await;
+    assert_eq!(started["presence"]["username"], json!(&white.username));
+    assert_eq!(started["presence"]["game"], json!(&id));
+
+    let following = http
+        .get(format!("http://{}/vue/following", server.addr))
+        .header("cookie", &fan.cookie)
+        .send()
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    assert_eq!(following[0]["online"], json!(true));
+    assert_eq!(following[0]["game"], json!(&id));
+
+    white.resign().await;
+    let ended = fan.expect(MessageType::Presence).await;
+    assert_eq!(ended["presence"]["game"], Value::Null);
+    server.stop().await;
+}
@@ -0,0 +1,26 @@
+mod common;
+
+use common::TestServer;
+use lishuuro::database::indexes::{
+    ensure_indexes, schema_version, set_schema_version, SCHEMA_VERSION,
+};
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn indexes_are_created_once() {
+    let server = TestServer::start().await;
+    let mongo = &server.state.db.mongo;
+    // Running again with same indexes is fine.
+    ensure_indexes(mongo).await.unwrap();
+    let names = mongo.games.list_index_names().await.unwrap();
+    assert!(names.contains(&String::from("players_status_last_clock")));
+    assert!(names.contains(&String::from("status")));
+    assert_eq!(schema_version(&mongo.schema).await, SCHEMA_VERSION);
+
+    set_schema_version(&mongo.schema, SCHEMA_VERSION + 1)
+        .await
+        .unwrap();
+    ensure_indexes(mongo).await.unwrap();
+    assert_eq!(schema_version(&mongo.schema).await, SCHEMA_VERSION + 1);
+    server.stop().await;
+}
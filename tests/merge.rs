@@ -0,0 +1,118 @@
+mod common;
+
+use bson::doc;
+use common::{challenge, start_game, vs_friend, TestClient, TestServer};
+use hyper::header::COOKIE;
+use lishuuro::{
+    database::merge::{merge_games, merge_offer, MergeOffer},
+    websockets::channels::{errors::ErrorCode, message_types::MessageType},
+};
+use serde_json::json;
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn guest_games_are_moved_to_account() {
+    let server = TestServer::start().await;
+    let db = &server.state.db.mongo.games;
+    let games = server.raw_games();
+    server
+        .seed_games(&[
+            ("finished", ["Anon-a", "b"], 7),
+            ("live", ["c", "Anon-a"], -1),
+            ("self", ["Anon-a", "reg"], 7),
+            ("other", ["b", "c"], 7),
+        ])
+        .await;
+
+    let offer = merge_offer(db, "Anon-a", "reg").await;
+    let expected = MergeOffer {
+        from: String::from("Anon-a"),
+        games: 2,
+    };
+    assert_eq!(offer, Some(expected));
+    assert_eq!(merge_games(db, "Anon-a", "reg").await, 2);
+    assert_eq!(merge_offer(db, "Anon-a", "reg").await, None);
+
+    let filter = doc! {"players": "reg"};
+    assert_eq!(games.count_documents(filter).await.unwrap(), 3);
+    let game = games.find_one(doc! {"_id": "live"}).await.unwrap().unwrap();
+    let players = game.get_array("players").unwrap();
+    assert_eq!(players[1].as_str(), Some("reg"));
+    // Game against account keeps guest name.
+    let game = games.find_one(doc! {"_id": "self"}).await.unwrap().unwrap();
+    let players = game.get_array("players").unwrap();
+    assert_eq!(players[0].as_str(), Some("Anon-a"));
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn live_game_is_merged_and_played_under_new_name() {
+    let server = TestServer::start().await;
+    let mut white = server.client().await;
+    let mut black = server.client().await;
+    let id = start_game(&mut white, &mut black).await;
+
+    assert_eq!(merge_into(&server, &white, "reg").await, 1);
+    let renamed = black.expect(MessageType::RenamePlayer).await;
+    assert_eq!(renamed["players"], json!(["reg", &black.username]));
+
+    // Page is reloaded with new name.
+    let mut reg = server
+        .connect(white.cookie.clone(), String::from("reg"))
+        .await;
+    reg.change_room(&format!("/game/{}", id)).await;
+    reg.select_move("+Q").await;
+    reg.select_move("c").await;
+    let confirmed = black.expect(MessageType::ConfirmSelection).await;
+    assert_eq!(confirmed["confirmed"], json!([true, false]));
+
+    let games = &server.state.db.mongo.games;
+    let game = games.find_one(doc! {"_id": &id}).await.unwrap().unwrap();
+    assert_eq!(game.players, [String::from("reg"), black.username.clone()]);
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn merged_player_is_still_playing() {
+    let server = TestServer::start().await;
+    let mut white = server.client().await;
+    let mut black = server.client().await;
+    let mut other = server.client().await;
+    let id = start_game(&mut white, &mut black).await;
+    assert_eq!(merge_into(&server, &white, "reg").await, 1);
+
+    let mut reg = server
+        .connect(white.cookie.clone(), String::from("reg"))
+        .await;
+    reg.change_room("home").await;
+    reg.add_game_request(vs_friend(&other.username)).await;
+    let error = reg.expect(MessageType::Error).await;
+    assert_eq!(error["code"], json!(ErrorCode::AlreadyPlaying));
+
+    // Account is free again once game ends.
+    reg.change_room(&format!("/game/{}", id)).await;
+    reg.resign().await;
+    black.expect(MessageType::GameEnd).await;
+    challenge(&mut reg, &mut other).await;
+    server.stop().await;
+}
+
+/// Log in `client` session as `username` and merge its guest games.
+async fn merge_into(
+    server: &TestServer,
+    client: &TestClient,
+    username: &str,
+) -> u64 {
+    server.login(client, username).await;
+    reqwest::Client::new()
+        .post(format!("http://{}/vue/merge", server.addr))
+        .header(COOKIE, &client.cookie)
+        .send()
+        .await
+        .unwrap()
+        .json::<u64>()
+        .await
+        .unwrap()
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use bson::{doc, DateTime};
+use common::TestServer;
+use lishuuro::database::{
+    clock::queries::get_player, indexes::schema_version,
+    migrations::run_migrations,
+};
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn dry_run_reports_and_run_applies_once() {
+    let server = TestServer::start().await;
+    let mongo = &server.state.db.mongo;
+    let users = server.raw_players();
+    server.seed_players(&[("old", true)], DateTime::now()).await;
+    server.seed_games(&[("missing", ["a", "b"], 1)]).await;
+    let games = server.raw_games();
+    let sub_variants = [("none", 100), ("fairy", 2), ("high", 7), ("low", -1)];
+    for (id, sub_variant) in sub_variants {
+        let game = doc! {"_id": id, "sub_variant": sub_variant};
+        games.insert_one(game).await.unwrap();
+    }
+
+    let reports = run_migrations(mongo, true).await.unwrap();
+    let users_report = reports
+        .iter()
+        .find(|report| report.id == "users_roles_blocked")
+        .unwrap();
+    assert_eq!(users_report.documents, vec![String::from("old")]);
+    let games_report = reports
+        .iter()
+        .find(|report| report.id == "games_sub_variant")
+        .unwrap();
+    let mut invalid = games_report.documents.clone();
+    invalid.sort();
+    assert_eq!(invalid, ["high", "low", "missing"]);
+    assert!(reports.iter().all(|report| !report.applied));
+    let raw = users.find_one(doc! {"_id": "old"}).await.unwrap().unwrap();
+    assert!(!raw.contains_key("roles"));
+
+    let reports = run_migrations(mongo, false).await.unwrap();
+    assert!(reports.iter().all(|report| report.applied));
+    let player = get_player(&mongo.players, "old").await.unwrap();
+    assert!(player.roles.is_empty() && player.blocked.is_empty());
+    let game = games.find_one(doc! {"_id": "high"}).await.unwrap().unwrap();
+    assert_eq!(game.get_i32("sub_variant").unwrap(), 100);
+    let last = reports.last().unwrap().version;
+    assert_eq!(schema_version(&mongo.schema).await, last);
+
+    assert!(run_migrations(mongo, false).await.unwrap().is_empty());
+    server.stop().await;
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use common::{
+    play_fight, play_placement, position, select_queens, start_game, TestServer,
+};
+use lishuuro::websockets::channels::message_types::MessageType;
+use serde_json::{json, Value};
+use shuuro::position::Sfen;
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn finished_game_can_be_replayed() {
+    let server = TestServer::start().await;
+    let mut white = server.client().await;
+    let mut black = server.client().await;
+    let id = start_game(&mut white, &mut black).await;
+    let url = format!("http://{}/vue/replay/{}", server.addr, id);
+    let http = reqwest::Client::new();
+    let res = http.get(&url).send().await.unwrap();
+    assert_eq!(res.status(), 404);
+
+    let start = select_queens(&mut white, &mut black).await;
+    let mut placement = position(&start);
+    // Stage, move and sfen of each position.
+    let mut expected = vec![(0, String::new(), start)];
+    let placed = play_placement(&mut white, &mut black, &mut placement).await;
+    expected.extend(placed.into_iter().map(|(m, sfen)| (1, m, sfen)));
+    let game_start = placement.generate_sfen();
+    let mut fight = position(&game_start);
+    expected.push((2, String::new(), game_start));
+    let moves = play_fight(&mut white, &mut black, &mut fight, 2).await;
+    expected.extend(moves.into_iter().map(|(m, sfen)| (2, m, sfen)));
+
+    white.resign().await;
+    let end = black.expect(MessageType::GameEnd).await;
+    assert_eq!(end["termination"], json!("resignation"));
+
+    let replay = http
+        .get(&url)
+        .send()
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    assert_eq!(replay["status"], json!(7));
+    let positions = replay["positions"].as_array().unwrap();
+    assert_eq!(positions.len(), expected.len());
+    for (position, (stage, game_move, sfen)) in positions.iter().zip(&expected) {
+        assert_eq!(position["stage"], json!(stage));
+        assert_eq!(position["game_move"], json!(game_move));
+        assert_eq!(position["sfen"], json!(sfen));
+        // Only moves are timed, starting positions are not.
+        let timed = position["move_time"]["spent"].is_u64();
+        assert_eq!(timed, !game_move.is_empty(), "{}", game_move);
+    }
+    server.stop().await;
+}
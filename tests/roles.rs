@@ -0,0 +1,72 @@
+mod common;
+
+use bson::DateTime;
+use common::TestServer;
+use hyper::header::COOKIE;
+use lishuuro::database::{model::Role, roles::change_role};
+use serde_json::{json, Value};
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn admin_routes_require_role_and_changes_are_audited() {
+    let server = TestServer::start().await;
+    let mongo = &server.state.db.mongo;
+    let client = server.client().await;
+    server
+        .seed_players(&[("boss", true)], DateTime::now())
+        .await;
+    let http = reqwest::Client::new();
+    let audit = format!("http://{}/admin/audit", server.addr);
+    let get_audit = || http.get(&audit).header(COOKIE, &client.cookie).send();
+
+    // Guest session is never admin.
+    assert_eq!(get_audit().await.unwrap().status(), 403);
+    server.login(&client, "boss").await;
+    assert_eq!(get_audit().await.unwrap().status(), 403);
+
+    let reason = Some(String::from("from config"));
+    let granted =
+        change_role(mongo, "config", "boss", Role::Admin, true, reason).await;
+    assert_eq!(granted, Some(true));
+    let res = get_audit().await.unwrap();
+    assert_eq!(res.status(), 200);
+    let entries = res.json::<Value>().await.unwrap();
+    assert_eq!(entries.as_array().unwrap().len(), 1);
+    assert_eq!(entries[0]["actor"], json!("config"));
+    assert_eq!(entries[0]["target"], json!("boss"));
+    assert_eq!(entries[0]["reason"], json!("from config"));
+    let action = json!({"type": "GrantRole", "content": "admin"});
+    assert_eq!(entries[0]["action"], action);
+
+    // Granting again changes nothing and is not audited.
+    let granted =
+        change_role(mongo, "config", "boss", Role::Admin, true, None).await;
+    assert_eq!(granted, Some(false));
+    // Admin can do what moderator can.
+    let sanctions = format!("http://{}/mod/sanctions/boss", server.addr);
+    let res = http
+        .get(&sanctions)
+        .header(COOKIE, &client.cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    // Roles are given only to registered players.
+    let change = json!({
+        "username": &client.username,
+        "role": "moderator",
+        "grant": true
+    });
+    let res = http
+        .post(format!("http://{}/admin/roles", server.addr))
+        .header(COOKIE, &client.cookie)
+        .json(&change)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+
+    let entries = get_audit().await.unwrap().json::<Value>().await.unwrap();
+    assert_eq!(entries.as_array().unwrap().len(), 1);
+    server.stop().await;
+}
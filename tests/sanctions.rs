@@ -0,0 +1,61 @@
+mod common;
+
+use bson::DateTime;
+use chrono::Duration;
+use common::{start_game, TestClient, TestServer};
+use hyper::header::COOKIE;
+use lishuuro::{
+    database::{clock::time_source::FakeClock, model::Role, roles::change_role},
+    websockets::channels::{errors::ErrorCode, message_types::MessageType},
+};
+use serde_json::json;
+
+async fn chat(client: &mut TestClient, text: &str) {
+    client.send(MessageType::ChatMessage, json!(text)).await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn mute_applies_to_next_message_and_expires_on_clock() {
+    let fake = FakeClock::default();
+    let server = TestServer::start_with_clock(fake.clock()).await;
+    let mut white = server.client().await;
+    let mut black = server.client().await;
+    start_game(&mut white, &mut black).await;
+    let moderator = server.client().await;
+    server
+        .seed_players(&[("boss", true)], DateTime::now())
+        .await;
+    server.login(&moderator, "boss").await;
+    let mongo = &server.state.db.mongo;
+    change_role(mongo, "config", "boss", Role::Moderator, true, None).await;
+
+    // Sanctions are cached after first message.
+    chat(&mut white, "hello").await;
+    let message = black.expect(MessageType::ChatMessage).await;
+    assert_eq!(message["message"]["message"], json!("hello"));
+
+    let mute = json!({
+        "username": &white.username,
+        "kind": "mute",
+        "reason": "spam",
+        "minutes": 1
+    });
+    let res = reqwest::Client::new()
+        .post(format!("http://{}/mod/sanctions", server.addr))
+        .header(COOKIE, &moderator.cookie)
+        .json(&mute)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    chat(&mut white, "muted").await;
+    let error = white.expect(MessageType::Error).await;
+    assert_eq!(error["code"], json!(ErrorCode::Muted));
+
+    fake.advance(Duration::minutes(1));
+    chat(&mut white, "back").await;
+    let message = black.expect(MessageType::ChatMessage).await;
+    assert_eq!(message["message"]["message"], json!("back"));
+    server.stop().await;
+}
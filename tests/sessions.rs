@@ -0,0 +1,130 @@
+mod common;
+
+use std::time::Duration;
+
+use bson::{doc, DateTime};
+use common::{vs_friend, TestServer};
+use hyper::header::COOKIE;
+use lishuuro::{
+    database::redis::{CookieValue, RedisCli, UserSession},
+    websockets::channels::message_types::MessageType,
+};
+use serde_json::Value;
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn revoked_session_is_closed() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    let http = reqwest::Client::new();
+    let url = format!("http://{}/vue/sessions", server.addr);
+    let sessions: Vec<Value> = http
+        .get(&url)
+        .header(COOKIE, &client.cookie)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0]["current"], Value::Bool(true));
+    let id = sessions[0]["id"].as_str().unwrap();
+    assert!(!client.cookie.contains(id));
+
+    let res = http
+        .delete(format!("{}/{}", url, id))
+        .header(COOKIE, &client.cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    client.expect_close().await;
+
+    // Old cookie now gets new anonymous player.
+    let user: Value = http
+        .get(format!("http://{}/vue_user", server.addr))
+        .header(COOKIE, &client.cookie)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_ne!(user["username"].as_str(), Some(client.username.as_str()));
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn revoked_socket_is_not_read() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    let friend = server.client().await;
+    client.change_room("home").await;
+    client.expect(MessageType::GameCount).await;
+    let http = reqwest::Client::new();
+    let url = format!("http://{}/vue/sessions", server.addr);
+    let sessions: Vec<Value> = http
+        .get(&url)
+        .header(COOKIE, &client.cookie)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = sessions[0]["id"].as_str().unwrap();
+    http.delete(format!("{}/{}", url, id))
+        .header(COOKIE, &client.cookie)
+        .send()
+        .await
+        .unwrap();
+
+    // Client ignores close frame and keeps sending.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let request = vs_friend(&friend.username);
+    client.try_send(MessageType::AddGameRequest, request).await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let challenges = &server.state.db.mongo.challenges;
+    assert_eq!(challenges.count_documents(doc! {}).await.unwrap(), 0);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn concurrent_sessions_are_listed() {
+    let redis = RedisCli::memory();
+    let at =
+        |seconds: i64| DateTime::from_millis(1_700_000_000_000 + seconds * 1000);
+    let new = |key: &str, now: DateTime| {
+        let cookie = CookieValue::default();
+        let session = UserSession::new("reg", key, true, "", cookie, now);
+        let mut redis = redis.clone();
+        let key = String::from(key);
+        async move { redis.set_session(&key, session, true).await }
+    };
+    tokio::join!(new("a", at(0)), new("b", at(1)));
+    let mut redis = redis.clone();
+    assert_eq!(listed(&mut redis).await, ["b", "a"]);
+    // Last seen is saved after five minutes.
+    let mut session = redis.get_session("a", at(301)).await.unwrap();
+    assert_eq!(listed(&mut redis).await, ["a", "b"]);
+
+    session.user_agent = String::from("agent");
+    redis.update_session("a", &session).await;
+    assert!(redis.delete_session("reg", "a").await);
+    assert!(!redis.delete_session("reg", "a").await);
+    // Deleted session is not created again by update.
+    redis.update_session("a", &session).await;
+    assert!(redis.get_session("a", at(302)).await.is_none());
+    assert_eq!(redis.user_sessions("reg").await.len(), 1);
+}
+
+/// Keys of sessions of `reg` in listed order.
+async fn listed(redis: &mut RedisCli) -> Vec<String> {
+    let sessions = redis.user_sessions("reg").await;
+    sessions
+        .into_iter()
+        .map(|session| session.session)
+        .collect()
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use bson::doc;
+use common::{
+    play_fight, play_placement, position, select_queens, start_game, TestServer,
+};
+use lishuuro::{
+    database::verify::{verify_games, Issue},
+    websockets::channels::message_types::MessageType,
+};
+use shuuro::position::Sfen;
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn broken_game_is_replayed_and_repaired() {
+    let server = TestServer::start().await;
+    let mut white = server.client().await;
+    let mut black = server.client().await;
+    let id = start_game(&mut white, &mut black).await;
+    let start = select_queens(&mut white, &mut black).await;
+    let mut placement = position(&start);
+    play_placement(&mut white, &mut black, &mut placement).await;
+    let mut fight = position(&placement.generate_sfen());
+    let moves = play_fight(&mut white, &mut black, &mut fight, 2).await;
+    let (_, sfen) = moves.last().unwrap();
+    black.resign().await;
+    white.expect(MessageType::GameEnd).await;
+
+    let games = &server.state.db.mongo.games;
+    let broken = doc! {"$set": {"sfen": "broken", "termination": ""}};
+    games.update_one(doc! {"_id": &id}, broken).await.unwrap();
+
+    let report = verify_games(games, false).await.unwrap();
+    assert_eq!(report.checked, 1);
+    assert_eq!(report.repaired, 0);
+    let issues = &report.games[0].issues;
+    assert!(matches!(issues[0], Issue::Termination { .. }));
+    assert!(matches!(issues[1], Issue::SfenMismatch { .. }));
+
+    let report = verify_games(games, true).await.unwrap();
+    assert_eq!(report.repaired, 1);
+    assert!(report.games[0].repaired);
+    let game = games.find_one(doc! {"_id": &id}).await.unwrap().unwrap();
+    assert_eq!(&game.sfen, sfen);
+    assert_eq!(game.termination, "resignation");
+
+    // Nothing left to repair.
+    let report = verify_games(games, true).await.unwrap();
+    assert_eq!(report.repaired, 0);
+    assert!(report.games.is_empty());
+    server.stop().await;
+}
@@ -0,0 +1,157 @@
+mod common;
+
+use std::time::Duration as StdDuration;
+
+use bson::doc;
+use chrono::Duration;
+use common::{
+    mover, next_placement, position, select_queens, start_game, TestServer,
+};
+use lishuuro::{
+    database::{clock::time_source::FakeClock, model::GameStatus},
+    websockets::channels::{errors::ErrorCode, message_types::MessageType},
+};
+use serde_json::{json, Value};
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn resign_ends_game_for_both_players() {
+    let server = TestServer::start().await;
+    let mut white = server.client().await;
+    let mut black = server.client().await;
+    start_game(&mut white, &mut black).await;
+
+    black.resign().await;
+    for client in [&mut white, &mut black] {
+        let end = client.expect(MessageType::GameEnd).await;
+        assert_eq!(end["status"], json!(7));
+        assert_eq!(end["result"], json!(1));
+    }
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn confirmed_selection_is_broadcast() {
+    let server = TestServer::start().await;
+    let mut white = server.client().await;
+    let mut black = server.client().await;
+    start_game(&mut white, &mut black).await;
+
+    white.select_move("+Q").await;
+    white.select_move("c").await;
+    let confirmed = black.expect(MessageType::ConfirmSelection).await;
+    assert_eq!(confirmed["confirmed"], json!([true, false]));
+
+    black.select_move("+q").await;
+    black.select_move("c").await;
+    let placement = white.expect(MessageType::RedirectToGame).await;
+    assert!(placement["sfen"].as_str().is_some());
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn placement_in_selection_is_rejected() {
+    let server = TestServer::start().await;
+    let mut white = server.client().await;
+    let mut black = server.client().await;
+    start_game(&mut white, &mut black).await;
+
+    // Still in selection, so placement is rejected.
+    white.place_piece("Q@b1").await;
+    let error = white.expect(MessageType::Error).await;
+    assert_eq!(error["code"], json!(ErrorCode::WrongStage));
+    assert_eq!(error["message_type"], json!(MessageType::PlacePiece));
+    assert_eq!(error["game_move"], json!("Q@b1"));
+    white.resign().await;
+    let end = black.expect(MessageType::GameEnd).await;
+    assert_eq!(end["status"], json!(7));
+    assert_eq!(end["result"], json!(0));
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn move_outside_game_is_rejected() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.change_room("home").await;
+
+    client.move_piece("e2_e4").await;
+    let error = client.expect(MessageType::Error).await;
+    assert_eq!(error["code"], json!(ErrorCode::NotInGame));
+    assert_eq!(error["message_type"], json!(MessageType::MovePiece));
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn selection_times_out_on_fake_clock() {
+    let fake = FakeClock::default();
+    let server = TestServer::start_with_clock(fake.clock()).await;
+    let mut white = server.client().await;
+    let mut black = server.client().await;
+    let id = start_game(&mut white, &mut black).await;
+    let games = &server.state.db.mongo.games;
+
+    // 3 minutes and 2 seconds, flag falls only after full second over.
+    fake.advance(Duration::milliseconds(182_500));
+    tokio::time::sleep(StdDuration::from_millis(200)).await;
+    let game = games.find_one(doc! {"_id": &id}).await.unwrap().unwrap();
+    assert_ne!(game.status, GameStatus::Timeout);
+
+    // Longer than any clock check interval.
+    fake.advance(Duration::seconds(10));
+    for client in [&mut white, &mut black] {
+        let end = client.expect(MessageType::GameEnd).await;
+        assert_eq!(end["status"], json!(GameStatus::Timeout));
+        assert_eq!(end["termination"], json!("time out"));
+    }
+    let game = games.find_one(doc! {"_id": &id}).await.unwrap().unwrap();
+    assert_eq!(game.status, GameStatus::Timeout);
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB, run with MONGO_TEST set and --ignored"]
+async fn rejected_move_does_not_charge_clock() {
+    let fake = FakeClock::default();
+    let server = TestServer::start_with_clock(fake.clock()).await;
+    let mut white = server.client().await;
+    let mut black = server.client().await;
+    let id = start_game(&mut white, &mut black).await;
+    let placement = position(&select_queens(&mut white, &mut black).await);
+    let before = live_game(&server, &id).await;
+
+    let (game_move, _) = next_placement(&mut placement.clone());
+    // Same placement with opponent's piece.
+    let (piece, square) = game_move.split_at(1);
+    let wrong = match piece.to_uppercase() == piece {
+        true => format!("{}{}", piece.to_lowercase(), square),
+        false => format!("{}{}", piece.to_uppercase(), square),
+    };
+    let player = mover(&placement, &mut white, &mut black);
+    fake.advance(Duration::seconds(10));
+    player.place_piece(&wrong).await;
+    let error = player.expect(MessageType::Error).await;
+    assert_eq!(error["code"], json!(ErrorCode::IllegalMove));
+    let after = live_game(&server, &id).await;
+    assert_eq!(after["tc"]["clocks"], before["tc"]["clocks"]);
+    assert_eq!(after["tc"]["last_click"], before["tc"]["last_click"]);
+
+    // Spent time counts from previous accepted move.
+    fake.advance(Duration::seconds(5));
+    player.place_piece(&game_move).await;
+    player.expect(MessageType::PlacePiece).await;
+    let game = live_game(&server, &id).await;
+    assert_eq!(game["move_times"][0][0]["spent"], json!(15_000));
+    server.stop().await;
+}
+
+/// Game as kept by its game task.
+async fn live_game(server: &TestServer, id: &str) -> Value {
+    let url = format!("http://{}/vue/game/{}", server.addr, id);
+    let res = reqwest::get(url).await.unwrap();
+    res.json::<Value>().await.unwrap()
+}
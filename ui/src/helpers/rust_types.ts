@@ -29,6 +29,50 @@ export enum MessageType {
 	NewPlayer,
 }
 
+export enum GameOutcome {
+	Win = "win",
+	Loss = "loss",
+	Draw = "draw",
+}
+
+export interface CrosstableGame {
+	_id: string;
+	variant: number;
+	status: number;
+	result: number;
+	players: [string, string];
+	/** Outcome for first player of crosstable. */
+	outcome: GameOutcome;
+	last_clock: string;
+}
+
+export interface Score {
+	wins: number;
+	losses: number;
+	draws: number;
+}
+
+export interface VariantScore {
+	variant: number;
+	score: Score;
+}
+
+/** Wins in a row, counted from latest game. */
+export interface Streak {
+	username: string;
+	count: number;
+}
+
+/** Record between two players, scores are for first player. */
+export interface Crosstable {
+	players: [string, string];
+	score: Score;
+	variants: VariantScore[];
+	/** Latest games first. */
+	games: CrosstableGame[];
+	streak?: Streak;
+}
+
 export interface RedirectToPlacement {
 	t: MessageType;
 	id: string;
@@ -169,4 +213,3 @@ export interface UserProfileGames {
 	player?: Player;
 	games?: ShuuroGame[];
 }
-